@@ -1,7 +1,11 @@
 
-use std::ffi::OsString;
-use std::path::PathBuf;
-use std::process::Command;
+mod binary;
+
+use std::ffi::{OsStr,OsString};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use std::process::{Command,ExitStatus,Output,Stdio};
 
 use serde::{Serialize,Deserialize};
 #[allow(unused_imports)]
@@ -9,12 +13,68 @@ use tracing::{info,debug,warn,error,trace,Level};
 use thiserror::Error;
 
 /// Non-runnable version of [`Command`] used for serialization.
-#[derive(Serialize,Deserialize)]
+///
+/// [`Command`] has no getters for its stdio configuration, so redirections have to be set here
+/// with [`CommandConfig::stdout()`] and [`CommandConfig::stderr()`] instead, and input for the
+/// command with [`CommandConfig::stdin()`].
+///
+/// The same goes for [`Command::env_clear()`]. Only variables explicitly set or removed on the
+/// [`Command`] are captured, so at wake time the command inherits the helper's environment on
+/// top of them unless [`CommandConfig::clear_env()`] is set.
+///
+/// Several commands can be chained with [`CommandConfig::then()`] to run one after another,
+/// instead of wrapping them all in `sh -c`.
+#[derive(Clone,Debug,Serialize,Deserialize)]
 pub struct CommandConfig {
     program: OsString,
+    #[serde(with = "os_path")]
     dir: Option<PathBuf>,
     env_vars: Vec<(OsString,Option<OsString>)>,
     args: Vec<OsString>,
+    #[serde(default, with = "os_path")]
+    stdout: Option<PathBuf>,
+    #[serde(default, with = "os_path")]
+    stderr: Option<PathBuf>,
+    #[serde(default)]
+    clear_env: bool,
+    #[serde(default)]
+    stdin: Option<Vec<u8>>,
+    #[serde(default)]
+    then: Vec<CommandConfig>,
+}
+
+/// serde writes paths as strings and refuses ones that aren't valid UTF-8, unlike `OsString`,
+/// which it writes as raw bytes. Paths keep the string form where they can, so existing payloads
+/// stay the same, and fall back to the `OsString` form otherwise.
+mod os_path {
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use serde::{Deserialize,Deserializer,Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Str(String),
+        Os(OsString),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok,S::Error> {
+        match path {
+            Some(path) => match path.to_str() {
+                Some(path) => serializer.serialize_some(path),
+                None => serializer.serialize_some(path.as_os_str()),
+            },
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>,D::Error> {
+        Ok(Option::<Repr>::deserialize(deserializer)?.map(|repr| match repr {
+            Repr::Str(path) => path.into(),
+            Repr::Os(path) => path.into(),
+        }))
+    }
 }
 
 impl From<Command> for CommandConfig {
@@ -30,14 +90,26 @@ impl From<Command> for CommandConfig {
             dir,
             env_vars,
             args,
+            stdout: None,
+            stderr: None,
+            clear_env: false,
+            stdin: None,
+            then: Vec::new(),
         }
     }
 }
 
+/// Note that this drops any stdio redirection, since opening the files is fallible, along with
+/// any stdin data and commands chained with [`CommandConfig::then()`]. Use
+/// [`CommandConfig::into_command()`] to get a [`Command`] with the redirections applied, or
+/// [`CommandConfig::output()`] to run it with everything.
 impl From<CommandConfig> for Command {
     fn from(config: CommandConfig) -> Self {
         let mut command = Command::new(config.program);
         command.args(config.args);
+        if config.clear_env {
+            command.env_clear();
+        }
         for (key, value) in config.env_vars {
             match value {
                 Some(value) => {
@@ -48,32 +120,308 @@ impl From<CommandConfig> for Command {
                 }
             }
         }
-        match config.dir {
-            Some(dir) => {
-                command.current_dir(dir);
+        if let Some(dir) = config.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+}
+
+impl CommandConfig {
+    /// Program the command runs.
+    pub fn program(&self) -> &OsStr {
+        &self.program
+    }
+
+    /// Programs of this command and of every command chained after it, in the order they run.
+    pub fn programs(&self) -> Vec<&OsStr> {
+        let mut programs = vec![self.program()];
+        programs.extend(self.then.iter().flat_map(CommandConfig::programs));
+        programs
+    }
+
+    /// Arguments passed to the program.
+    pub fn args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    /// Environment variables set on the command, with `None` for ones it removes.
+    pub fn env_vars(&self) -> &[(OsString,Option<OsString>)] {
+        &self.env_vars
+    }
+
+    /// Working directory the command runs in, if one was set.
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Redirects the command's stdout to the file at `path` when it runs, truncating it first.
+    pub fn stdout(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stdout = Some(path.into());
+        self
+    }
+
+    /// Redirects the command's stderr to the file at `path` when it runs, truncating it first.
+    pub fn stderr(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stderr = Some(path.into());
+        self
+    }
+
+    /// Feeds `data` to the command's stdin when it runs, e.g. a script for a shell.
+    pub fn stdin(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(data.into());
+        self
+    }
+
+    /// Starts the command with an empty environment, so only the variables set on it are
+    /// passed through.
+    pub fn clear_env(mut self, clear_env: bool) -> Self {
+        self.clear_env = clear_env;
+        self
+    }
+
+    /// Chains `next` to run once this command (and anything already chained) has succeeded. The
+    /// chain stops at the first command that fails, and its status is what the whole chain
+    /// reports.
+    pub fn then(mut self, next: impl Into<CommandConfig>) -> Self {
+        self.then.push(next.into());
+        self
+    }
+
+    /// Builds a runnable [`Command`], creating any redirection targets. Chained commands are
+    /// left out, see [`CommandConfig::then()`].
+    pub fn into_command(mut self) -> std::io::Result<Command> {
+        let stdout = self.stdout.take();
+        let stderr = self.stderr.take();
+        let mut command: Command = self.into();
+        if let Some(path) = stdout {
+            command.stdout(File::create(path)?);
+        }
+        if let Some(path) = stderr {
+            command.stderr(File::create(path)?);
+        }
+        Ok(command)
+    }
+
+    /// Runs the command to completion like [`Command::output()`], creating any redirection
+    /// targets and writing the [`CommandConfig::stdin()`] data to it. For a chain, the captured
+    /// output of every command that ran is concatenated and the status is the last one's.
+    pub fn output(self) -> std::io::Result<Output> {
+        self.run(true)
+    }
+
+    /// Same as [`CommandConfig::output()`], but like [`Command::status()`] any stdout or stderr
+    /// that isn't redirected is inherited instead of captured. This is what the systemd-wake
+    /// helper does at wake time, so the output lands in the journal.
+    pub fn status(self) -> std::io::Result<ExitStatus> {
+        Ok(self.run(false)?.status)
+    }
+
+    fn run(mut self, capture: bool) -> std::io::Result<Output> {
+        let then = std::mem::take(&mut self.then);
+        let mut output = self.run_one(capture)?;
+        for next in then {
+            if !output.status.success() {
+                break;
             }
-            None => {},
+            let next_output = next.run(capture)?;
+            output.stdout.extend(next_output.stdout);
+            output.stderr.extend(next_output.stderr);
+            output.status = next_output.status;
+        }
+        Ok(output)
+    }
+
+    fn run_one(mut self, capture: bool) -> std::io::Result<Output> {
+        let data = self.stdin.take();
+        let capture_stdout = capture && self.stdout.is_none();
+        let capture_stderr = capture && self.stderr.is_none();
+        let mut command = self.into_command()?;
+        if capture_stdout {
+            command.stdout(Stdio::piped());
+        }
+        if capture_stderr {
+            command.stderr(Stdio::piped());
+        }
+        let Some(data) = data else {
+            if capture {
+                // same as Command::output()
+                command.stdin(Stdio::null());
+            }
+            return command.spawn()?.wait_with_output();
         };
-        command
+
+        command.stdin(Stdio::piped());
+        let mut child = command.spawn()?;
+        let mut child_stdin = child.stdin.take().expect("stdin is piped");
+        // write from another thread so a child stuck on a full stdout pipe can't deadlock us
+        let writer = std::thread::spawn(move || child_stdin.write_all(&data));
+        let output = child.wait_with_output()?;
+        if let Ok(Err(e)) = writer.join() {
+            // the child is free to exit without reading everything
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl CommandConfig {
+    /// First program in the chain that can't be found, either at its path or on `PATH`. A `PATH`
+    /// set on the command itself takes precedence over `default_path`.
+    pub(crate) fn missing_program(&self, default_path: Option<&OsStr>) -> Option<&OsStr> {
+        let path = self.env_vars.iter()
+            .find(|(key, _)| key == "PATH")
+            .map_or(default_path, |(_, value)| value.as_deref());
+        let program = Path::new(&self.program);
+        let found = if program.components().count() > 1 {
+            match &self.dir {
+                Some(dir) if program.is_relative() => dir.join(program).is_file(),
+                _ => program.is_file(),
+            }
+        } else {
+            path.is_some_and(|path| std::env::split_paths(path).any(|dir| dir.join(program).is_file()))
+        };
+        if !found {
+            return Some(&self.program);
+        }
+        self.then.iter().find_map(|next| next.missing_program(default_path))
     }
 }
 
+/// Serialization format used inside the encoded command.
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq)]
+pub enum Format {
+    /// Plain JSON. Easy to inspect once unwrapped from base64.
+    #[default]
+    Json,
+    /// Compact tagged binary. Noticeably shorter for commands with many arguments or environment
+    /// variables.
+    Binary,
+}
+
+/// Longest encoded command [`CommandConfig::encode()`] and friends produce. It's handed to
+/// systemd-run as a single `--setenv=` argument, and Linux rejects any one argument longer than
+/// 128 KiB.
+pub const MAX_ENCODED_LEN: usize = 128 * 1024 - "--setenv=SYSTEMD_WAKE_COMMAND=".len() - 1;
+
+/// Leading byte that marks a binary payload. Can never start a JSON document.
+const BINARY_MAGIC: u8 = 0x01;
+
+/// Serialized commands are JSON (or the compact [`Format::Binary`]), wrapped in URL-safe
+/// unpadded base64 so they survive being passed around as a single command-line argument. Older
+/// releases wrapped the JSON in hex instead, which [`CommandConfig::decode()`] still accepts. The
+/// format is detected when decoding.
 #[allow(missing_docs)]
 impl CommandConfig {
-    pub fn encode(command: Command) -> Result<String,CommandConfigError> {
-        let config: CommandConfig = command.into();
-        let json = serde_json::to_string(&config)?;
-        Ok(hex::encode(json))
+    pub fn encode(command: impl Into<CommandConfig>) -> Result<String,CommandConfigError> {
+        Self::encode_with(command,Format::Json)
+    }
+
+    pub fn encode_binary(command: impl Into<CommandConfig>) -> Result<String,CommandConfigError> {
+        Self::encode_with(command,Format::Binary)
+    }
+
+    pub fn encode_with(command: impl Into<CommandConfig>, format: Format) -> Result<String,CommandConfigError> {
+        Self::encode_with_limit(command,format,MAX_ENCODED_LEN)
+    }
+
+    /// Fails with [`CommandConfigError::TooLarge`] if the result would be longer than `max_len`.
+    pub fn encode_with_limit(command: impl Into<CommandConfig>, format: Format, max_len: usize) -> Result<String,CommandConfigError> {
+        let mut config: CommandConfig = command.into();
+        // the helper runs wherever systemd puts it, so a relative dir has to be pinned down now
+        if let Some(dir) = config.dir.as_ref().filter(|dir| dir.is_relative()) {
+            let absolute = std::fs::canonicalize(dir).map_err(|source| CommandConfigError::Dir { dir: dir.clone(), source })?;
+            config.dir = Some(absolute);
+        }
+        let bytes = match format {
+            Format::Json => serde_json::to_vec(&config)?,
+            Format::Binary => {
+                let mut bytes = vec![BINARY_MAGIC];
+                binary::encode(&serde_json::to_value(&config)?,&mut bytes);
+                bytes
+            },
+        };
+        // unpadded base64 length
+        let len = (bytes.len() * 4).div_ceil(3);
+        if len > max_len {
+            return Err(CommandConfigError::TooLarge { len, max_len });
+        }
+        Ok(base64_encode(&bytes))
     }
     
-    pub fn decode(hexcode: impl AsRef<[u8]>) -> Result<Command,CommandConfigError> {
-        let bytes = hex::decode(hexcode)?;
+    pub fn decode(encoded: impl AsRef<[u8]>) -> Result<Command,CommandConfigError> {
+        Ok(Self::decode_config(encoded)?.into())
+    }
+
+    /// Accepts every format any release has produced: hex-wrapped JSON, and base64-wrapped JSON or
+    /// binary. The helper binary decodes through here, so it can run timers registered by older
+    /// and newer versions of the library alike.
+    pub fn decode_config(encoded: impl AsRef<[u8]>) -> Result<CommandConfig,CommandConfigError> {
+        let encoded = encoded.as_ref();
+        // hex digits are a subset of the base64 alphabet, so give the legacy format first try
+        if is_hex(encoded) {
+            if let Ok(config) = Self::from_json(hex::decode(encoded)?) {
+                return Ok(config);
+            }
+        }
+        let bytes = base64_decode(encoded)?;
+        match bytes.split_first() {
+            Some((&BINARY_MAGIC, mut rest)) => {
+                let value = binary::decode(&mut rest)?;
+                if !rest.is_empty() {
+                    return Err(CommandConfigError::Binary);
+                }
+                Ok(serde_json::from_value(value)?)
+            },
+            _ => Self::from_json(bytes),
+        }
+    }
+
+    fn from_json(bytes: Vec<u8>) -> Result<CommandConfig,CommandConfigError> {
         let json = String::from_utf8(bytes)?;
-        let config: CommandConfig = serde_json::from_str(&json)?;
-        Ok(config.into())
+        Ok(serde_json::from_str(&json)?)
     }
 }
 
+fn is_hex(encoded: &[u8]) -> bool {
+    encoded.len() % 2 == 0 && encoded.iter().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = 0u32;
+        for (i, byte) in chunk.iter().enumerate() {
+            group |= (*byte as u32) << (16 - 8 * i);
+        }
+        for i in 0..=chunk.len() {
+            encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+fn base64_decode(encoded: &[u8]) -> Result<Vec<u8>,CommandConfigError> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.chunks(4) {
+        if chunk.len() == 1 {
+            return Err(CommandConfigError::Base64);
+        }
+        let mut group = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|a| a == c).ok_or(CommandConfigError::Base64)?;
+            group |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            bytes.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
 
 /// Error type for CommandConfig.
 #[derive(Error,Debug)]
@@ -83,6 +431,227 @@ pub enum CommandConfigError {
     SerdeJson(#[from] serde_json::Error),
     #[error("hex (de/en)coding error")]
     Hex(#[from] hex::FromHexError),
+    #[error("base64 decoding error")]
+    Base64,
+    #[error("malformed binary encoding")]
+    Binary,
     #[error("utf8 parsing error")]
     Utf8(#[from] std::string::FromUtf8Error),
+    #[error("encoded command is {len} bytes, more than the limit of {max_len}")]
+    TooLarge {
+        len: usize,
+        max_len: usize,
+    },
+    #[error("can't resolve working directory {}", dir.display())]
+    Dir {
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redirect_round_trip() {
+        let config = CommandConfig::from(Command::new("true"))
+            .stdout("/tmp/out.log")
+            .stderr("/tmp/err.log");
+        let encoded = CommandConfig::encode(config).unwrap();
+        let decoded = CommandConfig::decode_config(encoded).unwrap();
+        assert_eq!(decoded.stdout,Some(PathBuf::from("/tmp/out.log")));
+        assert_eq!(decoded.stderr,Some(PathBuf::from("/tmp/err.log")));
+    }
+
+    #[test]
+    fn test_clear_env_round_trip() {
+        let config = CommandConfig::from(Command::new("true")).clear_env(true);
+        let decoded = CommandConfig::decode_config(CommandConfig::encode(config).unwrap()).unwrap();
+        assert!(decoded.clear_env);
+        // payloads from before the field existed
+        let legacy = base64_encode(br#"{"program":{"Unix":[116]},"dir":null,"env_vars":[],"args":[]}"#);
+        assert!(!CommandConfig::decode_config(legacy).unwrap().clear_env);
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut command = Command::new("ls");
+        command.arg("-l").env("LANG","C").env_remove("HOME").current_dir("/tmp");
+        let config = CommandConfig::from(command);
+        assert_eq!(config.program(),"ls");
+        assert_eq!(config.args(),["-l"]);
+        assert_eq!(config.dir(),Some(Path::new("/tmp")));
+        assert!(config.env_vars().contains(&("LANG".into(),Some("C".into()))));
+        assert!(config.env_vars().contains(&("HOME".into(),None)));
+    }
+
+    #[test]
+    fn test_relative_dir_resolved() {
+        let mut command = Command::new("true");
+        command.current_dir("src");
+        let decoded = CommandConfig::decode_config(CommandConfig::encode(command).unwrap()).unwrap();
+        assert_eq!(decoded.dir(),Some(std::fs::canonicalize("src").unwrap().as_path()));
+
+        let mut command = Command::new("true");
+        command.current_dir("no-such-directory");
+        assert!(matches!(CommandConfig::encode(command),Err(CommandConfigError::Dir { .. })));
+    }
+
+    #[test]
+    fn test_stdin_round_trip() {
+        let data: Vec<u8> = (0..=255).chain([0,0,255]).collect();
+        for format in [Format::Json,Format::Binary] {
+            let config = CommandConfig::from(Command::new("cat")).stdin(data.clone());
+            let decoded = CommandConfig::decode_config(CommandConfig::encode_with(config,format).unwrap()).unwrap();
+            assert_eq!(decoded.stdin.as_deref(),Some(&data[..]));
+            assert_eq!(decoded.output().unwrap().stdout,data);
+        }
+    }
+
+    #[test]
+    fn test_chain_stops_on_failure() {
+        let marker = std::env::temp_dir().join(format!("systemd-wake-chain-{}",std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let step = |script: &str| {
+            let mut command = Command::new("sh");
+            command.args(["-c",script]);
+            command
+        };
+        let touch = format!("touch {}",marker.display());
+        let config = CommandConfig::from(step("echo one"))
+            .then(step("echo two; exit 3"))
+            .then(step(&touch));
+        for format in [Format::Json,Format::Binary] {
+            let decoded = CommandConfig::decode_config(CommandConfig::encode_with(config.clone(),format).unwrap()).unwrap();
+            let output = decoded.output().unwrap();
+            assert_eq!(output.status.code(),Some(3));
+            assert_eq!(output.stdout,b"one\ntwo\n");
+            assert!(!marker.exists());
+        }
+
+        let config = CommandConfig::from(step("true")).then(step(&touch));
+        assert!(config.status().unwrap().success());
+        assert!(marker.exists());
+        std::fs::remove_file(&marker).unwrap();
+
+        let config = CommandConfig::from(Command::new("backup")).then(CommandConfig::from(Command::new("upload")).then(Command::new("notify")));
+        assert_eq!(config.programs(),["backup","upload","notify"]);
+    }
+
+    #[test]
+    fn test_missing_program() {
+        let path = std::env::var_os("PATH");
+        let path = path.as_deref();
+        assert_eq!(CommandConfig::from(Command::new("sh")).missing_program(path),None);
+        assert_eq!(CommandConfig::from(Command::new("/bin/sh")).missing_program(path),None);
+        let missing = CommandConfig::from(Command::new("sh")).then(Command::new("systemd-wake-test-no-such-binary"));
+        assert_eq!(missing.missing_program(path),Some(OsStr::new("systemd-wake-test-no-such-binary")));
+        assert!(CommandConfig::from(Command::new("/no/such/sh")).missing_program(path).is_some());
+
+        let mut command = Command::new("sh");
+        command.env("PATH","/no/such/dir");
+        assert!(CommandConfig::from(command).missing_program(path).is_some());
+        let mut command = Command::new("./command.rs");
+        command.current_dir(std::fs::canonicalize("src").unwrap());
+        assert_eq!(CommandConfig::from(command).missing_program(None),None);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut command = Command::new("true");
+        command.arg("x".repeat(MAX_ENCODED_LEN));
+        assert!(matches!(CommandConfig::encode(command),Err(CommandConfigError::TooLarge { max_len: MAX_ENCODED_LEN, .. })));
+
+        let encoded = CommandConfig::encode(Command::new("true")).unwrap();
+        assert!(CommandConfig::encode_with_limit(Command::new("true"),Format::Json,encoded.len()).is_ok());
+        match CommandConfig::encode_with_limit(Command::new("true"),Format::Json,encoded.len() - 1) {
+            Err(CommandConfigError::TooLarge { len, .. }) => assert_eq!(len,encoded.len()),
+            other => panic!("expected TooLarge, got {:?}",other),
+        }
+    }
+
+    #[test]
+    fn test_base64() {
+        for input in [&b""[..],b"f",b"fo",b"foo",b"foob",b"fooba",b"foobar",&[0xff,0xfe,0x00]] {
+            assert_eq!(base64_decode(base64_encode(input).as_bytes()).unwrap(),input);
+        }
+        assert_eq!(base64_encode(b"foobar"),"Zm9vYmFy");
+        assert_eq!(base64_encode(&[0xfb,0xff]),"-_8");
+        assert!(base64_decode(b"Zm9vY").is_err());
+        assert!(base64_decode(b"Zm9v!").is_err());
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let command = || {
+            let mut command = Command::new("printf");
+            command.args(["%s\n","one","two"]).env("LANG","C").env_remove("HOME").current_dir("/tmp");
+            command
+        };
+        let json = CommandConfig::encode(command()).unwrap();
+        let binary = CommandConfig::encode_binary(command()).unwrap();
+        assert!(binary.len() < json.len());
+
+        let json = CommandConfig::decode_config(json).unwrap();
+        let binary = CommandConfig::decode_config(binary).unwrap();
+        assert_eq!(serde_json::to_value(json).unwrap(),serde_json::to_value(binary).unwrap());
+    }
+
+    #[test]
+    fn test_decode_cross_format() {
+        let commands = || {
+            let mut plain = Command::new("true");
+            plain.arg("");
+            let mut busy = Command::new("printf");
+            busy.args(["%s\n","héllo","ffff"]).env("A","0123456789abcdef").current_dir("/tmp");
+            [plain,busy]
+        };
+        for command in commands() {
+            let config = CommandConfig::from(command);
+            let expected = serde_json::to_value(&config).unwrap();
+            let encodings = [
+                hex::encode(serde_json::to_vec(&config).unwrap()),
+                CommandConfig::encode(config.clone()).unwrap(),
+                CommandConfig::encode_binary(config.clone()).unwrap(),
+            ];
+            for encoded in encodings {
+                let decoded = CommandConfig::decode_config(&encoded).unwrap();
+                assert_eq!(serde_json::to_value(decoded).unwrap(),expected,"{}",encoded);
+            }
+        }
+        // looks like hex, but isn't a payload in either format
+        assert!(CommandConfig::decode_config("00").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_non_utf8_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let bytes = OsStr::from_bytes(b"caf\xe9");
+        for format in [Format::Json,Format::Binary] {
+            let mut command = Command::new(bytes);
+            command.arg(bytes).env(bytes,bytes).current_dir("/");
+            let config = CommandConfig::from(command).stdout(Path::new(bytes));
+            let config = CommandConfig::decode_config(CommandConfig::encode_with(config,format).unwrap()).unwrap();
+            assert_eq!(config.program(),bytes);
+            assert_eq!(config.args(),[bytes]);
+            assert_eq!(config.env_vars(),[(bytes.to_os_string(),Some(bytes.to_os_string()))]);
+            assert_eq!(config.stdout.as_deref(),Some(Path::new(bytes)));
+            assert_eq!(config.dir(),Some(Path::new("/")));
+        }
+        // paths that are valid UTF-8 keep their plain string form
+        let config = CommandConfig::from(Command::new("true")).stdout("/tmp/out");
+        assert_eq!(serde_json::to_value(&config).unwrap()["stdout"],"/tmp/out");
+    }
+
+    #[test]
+    fn test_decode_legacy_hex() {
+        let mut command = Command::new("echo");
+        command.arg("hello");
+        let json = serde_json::to_string(&CommandConfig::from(command)).unwrap();
+        let decoded = CommandConfig::decode_config(hex::encode(json)).unwrap();
+        assert_eq!(decoded.program,"echo");
+        assert_eq!(decoded.args,vec![OsString::from("hello")]);
+    }
 }
@@ -0,0 +1,174 @@
+//! Compact tagged binary encoding of a [`serde_json::Value`] tree.
+//!
+//! Working on the value tree rather than on [`CommandConfig`](super::CommandConfig) directly means
+//! new fields are picked up for free. The big win over JSON text is byte arrays (which is how
+//! `OsString`s serialize): each byte costs one byte instead of up to four characters.
+
+use serde_json::{Map,Number,Value};
+
+use super::CommandConfigError;
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const UNSIGNED: u8 = 3;
+const NEGATIVE: u8 = 4;
+const FLOAT: u8 = 5;
+const STRING: u8 = 6;
+const ARRAY: u8 = 7;
+const OBJECT: u8 = 8;
+const BYTES: u8 = 9;
+
+pub(super) fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(NULL),
+        Value::Bool(false) => out.push(FALSE),
+        Value::Bool(true) => out.push(TRUE),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                out.push(UNSIGNED);
+                encode_varint(n,out);
+            } else if let Some(n) = number.as_i64() {
+                out.push(NEGATIVE);
+                encode_varint(!(n as u64),out);
+            } else {
+                out.push(FLOAT);
+                out.extend_from_slice(&number.as_f64().unwrap_or_default().to_le_bytes());
+            }
+        },
+        Value::String(string) => {
+            out.push(STRING);
+            encode_str(string,out);
+        },
+        Value::Array(array) => {
+            if let Some(bytes) = as_bytes(array) {
+                out.push(BYTES);
+                encode_varint(bytes.len() as u64,out);
+                out.extend_from_slice(&bytes);
+            } else {
+                out.push(ARRAY);
+                encode_varint(array.len() as u64,out);
+                for item in array {
+                    encode(item,out);
+                }
+            }
+        },
+        Value::Object(object) => {
+            out.push(OBJECT);
+            encode_varint(object.len() as u64,out);
+            for (key, item) in object {
+                encode_str(key,out);
+                encode(item,out);
+            }
+        },
+    }
+}
+
+pub(super) fn decode(input: &mut &[u8]) -> Result<Value,CommandConfigError> {
+    Ok(match take(input,1)?[0] {
+        NULL => Value::Null,
+        FALSE => Value::Bool(false),
+        TRUE => Value::Bool(true),
+        UNSIGNED => Value::Number(decode_varint(input)?.into()),
+        NEGATIVE => Value::Number((!decode_varint(input)? as i64).into()),
+        FLOAT => {
+            let bytes = take(input,8)?.try_into().map_err(|_| CommandConfigError::Binary)?;
+            let number = Number::from_f64(f64::from_le_bytes(bytes)).ok_or(CommandConfigError::Binary)?;
+            Value::Number(number)
+        },
+        STRING => Value::String(decode_str(input)?),
+        ARRAY => {
+            let len = decode_len(input)?;
+            let mut array = Vec::with_capacity(len.min(input.len()));
+            for _ in 0..len {
+                array.push(decode(input)?);
+            }
+            Value::Array(array)
+        },
+        OBJECT => {
+            let len = decode_len(input)?;
+            let mut object = Map::new();
+            for _ in 0..len {
+                let key = decode_str(input)?;
+                object.insert(key,decode(input)?);
+            }
+            Value::Object(object)
+        },
+        BYTES => {
+            let len = decode_len(input)?;
+            Value::Array(take(input,len)?.iter().map(|byte| Value::from(*byte)).collect())
+        },
+        _ => return Err(CommandConfigError::Binary),
+    })
+}
+
+/// Arrays made up entirely of small integers are stored as raw bytes.
+fn as_bytes(array: &[Value]) -> Option<Vec<u8>> {
+    if array.is_empty() {
+        return None;
+    }
+    array.iter().map(|item| item.as_u64().and_then(|n| u8::try_from(n).ok())).collect()
+}
+
+fn encode_str(string: &str, out: &mut Vec<u8>) {
+    encode_varint(string.len() as u64,out);
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn decode_str(input: &mut &[u8]) -> Result<String,CommandConfigError> {
+    let len = decode_len(input)?;
+    Ok(String::from_utf8(take(input,len)?.to_vec())?)
+}
+
+fn encode_varint(mut n: u64, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn decode_varint(input: &mut &[u8]) -> Result<u64,CommandConfigError> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(input,1)?[0];
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(CommandConfigError::Binary)
+}
+
+fn decode_len(input: &mut &[u8]) -> Result<usize,CommandConfigError> {
+    usize::try_from(decode_varint(input)?).map_err(|_| CommandConfigError::Binary)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8],CommandConfigError> {
+    if input.len() < len {
+        return Err(CommandConfigError::Binary);
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_value_round_trip() {
+        let value = serde_json::json!({
+            "program": {"Unix": [101,99,104,111]},
+            "dir": null,
+            "args": [{"Unix": []}, "text", -7, 300, 1.5, true, false],
+        });
+        let mut encoded = Vec::new();
+        encode(&value,&mut encoded);
+        let mut input = &encoded[..];
+        assert_eq!(decode(&mut input).unwrap(),value);
+        assert!(input.is_empty());
+        assert!(decode(&mut &encoded[..encoded.len() - 1]).is_err());
+    }
+}
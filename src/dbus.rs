@@ -0,0 +1,459 @@
+//! D-Bus versions of [`register()`](crate::register), [`deregister()`](crate::deregister) and
+//! [`query_registration()`](crate::query_registration).
+//!
+//! These talk to `org.freedesktop.systemd1` directly instead of running `systemd-run` and
+//! `systemctl` and parsing what they print, so they don't depend on `PATH`, the locale or the
+//! output format of either tool. Like systemd-run, the timer and its service are created in a
+//! single `StartTransientUnit` call, and queries read typed properties off the bus. The service
+//! still runs the systemd-wake helper.
+//!
+//! Timers end up the same whichever way they were created, so one backend can query or cancel
+//! what the other registered. Raw [`RegisterOptions::extra_properties`] and
+//! [`RegisterOptions::extra_timer_properties`] can't be sent over the bus, and the
+//! [`RegisterOptions::linger_check`] still needs `loginctl`, so those fail with
+//! [`RegistrationError::InvalidOption`] here.
+
+use std::ffi::OsString;
+use std::path::{Path,PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use tracing::{debug,instrument};
+use zbus::blocking::Connection;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{OwnedObjectPath,Value};
+
+use crate::command::CommandConfig;
+use crate::{CommandError,IoClass,LingerCheck,MonotonicBase,MonotonicTrigger,QueryError,Registration,RegistrationError,RegisterOptions,Schedule,Scope,TimerSpec,UnitName,COMMAND_ENV_VAR};
+
+#[zbus::proxy(interface = "org.freedesktop.systemd1.Manager", default_service = "org.freedesktop.systemd1", default_path = "/org/freedesktop/systemd1", gen_async = false)]
+trait Manager {
+    fn start_transient_unit(&self, name: &str, mode: &str, properties: Vec<(&str, Value<'_>)>, aux: Vec<(&str, Vec<(&str, Value<'_>)>)>) -> zbus::Result<OwnedObjectPath>;
+
+    fn load_unit(&self, name: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn stop_unit(&self, name: &str, mode: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn reset_failed_unit(&self, name: &str) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(interface = "org.freedesktop.systemd1.Unit", default_service = "org.freedesktop.systemd1", gen_async = false)]
+trait Unit {
+    #[zbus(property)]
+    fn load_state(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn description(&self) -> zbus::Result<String>;
+
+    #[zbus(property, name = "InvocationID")]
+    fn invocation_id(&self) -> zbus::Result<Vec<u8>>;
+}
+
+#[zbus::proxy(interface = "org.freedesktop.systemd1.Timer", default_service = "org.freedesktop.systemd1", gen_async = false)]
+trait Timer {
+    #[zbus(property)]
+    fn timers_calendar(&self) -> zbus::Result<Vec<(String,String,u64)>>;
+
+    #[zbus(property)]
+    fn timers_monotonic(&self) -> zbus::Result<Vec<(String,u64,u64)>>;
+
+    #[zbus(property, name = "NextElapseUSecRealtime")]
+    fn next_elapse_realtime(&self) -> zbus::Result<u64>;
+}
+
+#[zbus::proxy(interface = "org.freedesktop.systemd1.Service", default_service = "org.freedesktop.systemd1", gen_async = false)]
+trait Service {
+    #[zbus(property)]
+    fn environment(&self) -> zbus::Result<Vec<String>>;
+}
+
+/// D-Bus version of [`register()`](crate::register).
+pub fn register<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<Registration,RegistrationError> {
+    register_with_options(schedule,unit_name,command,&RegisterOptions::default())
+}
+
+/// D-Bus version of [`register_scoped()`](crate::register_scoped).
+pub fn register_scoped<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, scope: Scope) -> Result<Registration,RegistrationError> {
+    let options = RegisterOptions {
+        scope,
+        ..Default::default()
+    };
+    register_with_options(schedule,unit_name,command,&options)
+}
+
+/// D-Bus version of [`register_with_options()`](crate::register_with_options). See the
+/// [module docs](self) for the options it can't handle.
+pub fn register_with_options<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    let encoded_command = crate::encode_command(command,options)?;
+    register_with(schedule.into(),unit_name.into(),&encoded_command,options)
+}
+
+#[instrument(name = "register", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn register_with(schedule: Schedule, unit_name: UnitName, encoded_command: &str, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    debug!("registering timer over D-Bus");
+
+    check_supported(options)?;
+    options.validate()?;
+    crate::check_waketime(&schedule,options)?;
+    let helper = helper_path(options)?;
+
+    let connection = connect(options.scope)?;
+    let manager = ManagerProxy::new(&connection).map_err(CommandError::from)?;
+    let timer_unit = unit_name.timer_unit();
+    let service_unit = unit_name.service_unit();
+    manager.start_transient_unit(&timer_unit,"fail",timer_properties(&schedule,unit_name,options),vec![(&service_unit,service_properties(unit_name,encoded_command,&helper,options))])
+        .map_err(start_error)?;
+
+    // the timer exists by now, so don't fail the registration over these
+    let path = manager.load_unit(&timer_unit).map_err(CommandError::from)?;
+    let next_elapse = timer_proxy(&connection,&path)
+        .and_then(|timer| timer.next_elapse_realtime())
+        .map_err(|e| debug!("couldn't read next elapse: {}",e))
+        .ok()
+        .and_then(|usec| crate::from_usec(usec.try_into().ok()?));
+    let invocation_id = unit_proxy(&connection,&path)
+        .and_then(|unit| unit.invocation_id())
+        .map_err(|e| debug!("couldn't read invocation ID: {}",e))
+        .ok()
+        .filter(|id| !id.is_empty())
+        .map(|id| id.iter().map(|byte| format!("{:02x}",byte)).collect());
+
+    debug!("registered {} -> {}",timer_unit,service_unit);
+    Ok(Registration {
+        unit_name: unit_name.into(),
+        timer_unit,
+        service_unit,
+        schedule,
+        next_elapse,
+        invocation_id,
+    })
+}
+
+/// D-Bus version of [`deregister()`](crate::deregister).
+pub fn deregister<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(Command,TimerSpec),RegistrationError> {
+    deregister_scoped(unit_name,Scope::User)
+}
+
+/// D-Bus version of [`deregister_scoped()`](crate::deregister_scoped).
+pub fn deregister_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(Command,TimerSpec),RegistrationError> {
+    deregister_with(&connect(scope)?,unit_name.into())
+}
+
+#[instrument(name = "deregister", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn deregister_with(connection: &Connection, unit_name: UnitName) -> Result<(Command,TimerSpec),RegistrationError> {
+    let (config, timer_spec) = query_with(connection,unit_name)?;
+
+    debug!("deregistering timer over D-Bus");
+    let manager = ManagerProxy::new(connection).map_err(CommandError::from)?;
+    match manager.stop_unit(&unit_name.timer_unit(),"replace") {
+        Ok(_) => (),
+        // unloaded between being looked up and being stopped, which is just as good
+        Err(e) if error_name(&e) == Some("org.freedesktop.systemd1.NoSuchUnit") => debug!("timer already gone: {}",e),
+        Err(e) => return Err(CommandError::from(e).into()),
+    }
+    for unit in [unit_name.service_unit(),unit_name.timer_unit()] {
+        if let Err(e) = manager.reset_failed_unit(&unit) {
+            debug!("nothing reset for {}: {}",unit,e);
+        }
+    }
+    Ok((config.into(),timer_spec))
+}
+
+/// D-Bus version of [`query_registration()`](crate::query_registration).
+pub fn query_registration<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(Command,TimerSpec),QueryError> {
+    query_registration_scoped(unit_name,Scope::User)
+}
+
+/// D-Bus version of [`query_registration_scoped()`](crate::query_registration_scoped).
+pub fn query_registration_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(Command,TimerSpec),QueryError> {
+    let connection = connect(scope)?;
+    let (config, timer_spec) = query_with(&connection,unit_name.into())?;
+    Ok((config.into(),timer_spec))
+}
+
+#[instrument(name = "query_registration", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn query_with(connection: &Connection, unit_name: UnitName) -> Result<(CommandConfig,TimerSpec),QueryError> {
+    debug!("querying registration over D-Bus");
+
+    let manager = ManagerProxy::new(connection).map_err(CommandError::from)?;
+    let timer_path = manager.load_unit(&unit_name.timer_unit()).map_err(CommandError::from)?;
+    let timer_unit = unit_proxy(connection,&timer_path).map_err(CommandError::from)?;
+    if timer_unit.load_state().map_err(CommandError::from)? != "loaded" {
+        return Err(QueryError::NotLoaded);
+    }
+    let timer = timer_proxy(connection,&timer_path).map_err(CommandError::from)?;
+    let timers_calendar = timer.timers_calendar().map_err(CommandError::from)?;
+    let timers_monotonic = timer.timers_monotonic().map_err(CommandError::from)?;
+
+    // the service is only there to read the command from if it's loaded
+    let service_path = manager.load_unit(&unit_name.service_unit()).map_err(CommandError::from)?;
+    let environment = match unit_proxy(connection,&service_path).and_then(|service| service.load_state()).map_err(CommandError::from)?.as_str() {
+        "loaded" => service_proxy(connection,&service_path).and_then(|service| service.environment()).map_err(CommandError::from)?,
+        _ => Vec::new(),
+    };
+    let encoded_command = match command_from_environment(&environment) {
+        Some(encoded_command) => encoded_command.to_owned(),
+        None => crate::description_payload(&timer_unit.description().map_err(CommandError::from)?)?,
+    };
+    let config = CommandConfig::decode_config(encoded_command)?;
+    Ok((config,timer_spec(&timers_calendar,&timers_monotonic)))
+}
+
+fn connect(scope: Scope) -> Result<Connection,CommandError> {
+    crate::check_platform()?;
+    let connection = match scope {
+        Scope::User => Connection::session(),
+        Scope::System => Connection::system(),
+    };
+    Ok(connection?)
+}
+
+fn unit_proxy<'c>(connection: &'c Connection, path: &OwnedObjectPath) -> zbus::Result<UnitProxy<'c>> {
+    UnitProxy::builder(connection).path(path.clone())?.cache_properties(CacheProperties::No).build()
+}
+
+fn timer_proxy<'c>(connection: &'c Connection, path: &OwnedObjectPath) -> zbus::Result<TimerProxy<'c>> {
+    TimerProxy::builder(connection).path(path.clone())?.cache_properties(CacheProperties::No).build()
+}
+
+fn service_proxy<'c>(connection: &'c Connection, path: &OwnedObjectPath) -> zbus::Result<ServiceProxy<'c>> {
+    ServiceProxy::builder(connection).path(path.clone())?.cache_properties(CacheProperties::No).build()
+}
+
+fn error_name(error: &zbus::Error) -> Option<&str> {
+    match error {
+        zbus::Error::MethodError(name, _, _) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+/// Same idea as [`run_error()`](crate::run_error): systemd refusing to create a unit that exists
+/// says which one it was.
+fn start_error(error: zbus::Error) -> RegistrationError {
+    if let zbus::Error::MethodError(name, Some(message), _) = &error {
+        if name.as_str() == "org.freedesktop.systemd1.UnitExists" {
+            if let Some(existing) = crate::existing_unit_error(message) {
+                return existing;
+            }
+        }
+    }
+    CommandError::from(error).into()
+}
+
+fn check_supported(options: &RegisterOptions) -> Result<(),RegistrationError> {
+    let unsupported = if !options.extra_properties.is_empty() {
+        "extra_properties"
+    } else if !options.extra_timer_properties.is_empty() {
+        "extra_timer_properties"
+    } else if options.linger_check != LingerCheck::Skip {
+        "linger_check"
+    } else {
+        return Ok(());
+    };
+    Err(RegistrationError::InvalidOption(format!("{} isn't supported by the D-Bus backend",unsupported)))
+}
+
+/// systemd wants an absolute `ExecStart=` path, and unlike systemd-run there's nobody to look
+/// the helper up on `PATH` for us, so do what systemd-run would.
+fn helper_path(options: &RegisterOptions) -> Result<String,RegistrationError> {
+    let helper = options.helper_program();
+    let path = if Path::new(&helper).components().count() > 1 {
+        std::fs::canonicalize(&helper).ok()
+    } else {
+        std::env::var_os("PATH").and_then(|path| std::env::split_paths(&path).map(|dir| dir.join(&helper)).find(|path| path.is_file()))
+    };
+    let path = path.ok_or_else(|| RegistrationError::ProgramNotFound(helper.clone()))?;
+    path.into_os_string().into_string()
+        .map_err(|path: OsString| RegistrationError::InvalidOption(format!("helper path {} isn't valid UTF-8",PathBuf::from(path).display())))
+}
+
+/// Microseconds, the unit of every `...USec` property. Like [`SystemdDuration`](crate::duration::SystemdDuration),
+/// anything shorter but not zero becomes one.
+fn usec(duration: Duration) -> u64 {
+    if duration.is_zero() {
+        0
+    } else {
+        u64::try_from(duration.as_micros()).unwrap_or(u64::MAX).max(1)
+    }
+}
+
+fn description(unit_name: UnitName, options: &RegisterOptions) -> String {
+    match &options.description {
+        Some(description) => description.clone(),
+        None => format!("systemd-wake {}",unit_name),
+    }
+}
+
+/// The timer half of what systemd-run sends, see `org.freedesktop.systemd1(5)`. Monotonic entries
+/// are set with their `...Sec` names, even though systemd reports them back as `...USec`.
+fn timer_properties(schedule: &Schedule, unit_name: UnitName, options: &RegisterOptions) -> Vec<(&'static str, Value<'static>)> {
+    let mut properties = vec![("Description",Value::from(description(unit_name,options)))];
+    let monotonic = |entries: &[(&str, Duration)]| Value::from(entries.iter().map(|(base, offset)| (base.to_string(),usec(*offset))).collect::<Vec<_>>());
+    properties.push(match schedule {
+        Schedule::At(event_time) => ("TimersCalendar",Value::from(vec![("OnCalendar".to_owned(),crate::format_calendar_time(event_time))])),
+        Schedule::Calendar(calendar) => ("TimersCalendar",Value::from(vec![("OnCalendar".to_owned(),calendar.clone())])),
+        Schedule::After(offset) => ("TimersMonotonic",monotonic(&[("OnActiveSec",*offset)])),
+        Schedule::OnBoot(offset) => ("TimersMonotonic",monotonic(&[("OnBootSec",*offset)])),
+        Schedule::OnStartup(offset) => ("TimersMonotonic",monotonic(&[("OnStartupSec",*offset)])),
+        Schedule::Every(interval) => ("TimersMonotonic",monotonic(&[("OnActiveSec",*interval),("OnUnitActiveSec",*interval)])),
+    });
+    if options.persistent {
+        properties.push(("Persistent",Value::from(true)));
+    }
+    if let Some(accuracy) = options.accuracy {
+        properties.push(("AccuracyUSec",Value::from(usec(accuracy))));
+    }
+    if let Some(randomized_delay) = options.randomized_delay {
+        properties.push(("RandomizedDelayUSec",Value::from(usec(randomized_delay))));
+    }
+    if let Some(remain_after_elapse) = options.remain_after_elapse {
+        properties.push(("RemainAfterElapse",Value::from(remain_after_elapse)));
+    }
+    properties
+}
+
+/// The service half of what systemd-run sends, covering the same typed options as
+/// [`RegisterOptions::service_properties()`](crate::RegisterOptions).
+fn service_properties(unit_name: UnitName, encoded_command: &str, helper: &str, options: &RegisterOptions) -> Vec<(&'static str, Value<'static>)> {
+    let mut environment = vec![format!("{}={}",COMMAND_ENV_VAR,encoded_command)];
+    environment.extend(options.environment.iter().map(|(key, value)| format!("{}={}",key,value)));
+    let mut properties = vec![
+        ("Description",Value::from(description(unit_name,options))),
+        ("Environment",Value::from(environment)),
+        ("SyslogIdentifier",Value::from(unit_name.to_string())),
+        ("ExecStart",Value::from(vec![(helper.to_owned(),vec![helper.to_owned()],false)])),
+    ];
+    if let Some(on_failure) = &options.on_failure {
+        properties.push(("OnFailure",Value::from(vec![on_failure.clone()])));
+    }
+    if let Some(nice) = options.nice {
+        properties.push(("Nice",Value::from(i32::from(nice))));
+    }
+    if let Some(io_class) = options.io_class {
+        // IOPRIO_CLASS_* values
+        let io_class: i32 = match io_class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        properties.push(("IOSchedulingClass",Value::from(io_class)));
+    }
+    if let Some(memory_max) = options.memory_max {
+        properties.push(("MemoryMax",Value::from(memory_max)));
+    }
+    if let Some(cpu_quota) = options.cpu_quota {
+        // percent of one CPU, as CPU time per second
+        properties.push(("CPUQuotaPerSecUSec",Value::from(u64::from(cpu_quota) * 10_000)));
+    }
+    if let Some(tasks_max) = options.tasks_max {
+        properties.push(("TasksMax",Value::from(tasks_max)));
+    }
+    if let Some(restart) = options.restart {
+        properties.push(("Restart",Value::from(restart.as_str())));
+    }
+    if let Some(restart_delay) = options.restart_delay {
+        properties.push(("RestartUSec",Value::from(usec(restart_delay))));
+    }
+    if let Some(watchdog) = options.watchdog {
+        properties.push(("WatchdogUSec",Value::from(usec(watchdog))));
+    }
+    if let Some(runtime_max) = options.runtime_max {
+        properties.push(("RuntimeMaxUSec",Value::from(usec(runtime_max))));
+    }
+    if let Some(user) = &options.user {
+        properties.push(("User",Value::from(user.clone())));
+    }
+    if let Some(group) = &options.group {
+        properties.push(("Group",Value::from(group.clone())));
+    }
+    if let Some(slice) = options.slice_unit() {
+        properties.push(("Slice",Value::from(slice)));
+    }
+    properties
+}
+
+fn command_from_environment(environment: &[String]) -> Option<&str> {
+    environment.iter().find_map(|pair| pair.strip_prefix(COMMAND_ENV_VAR)?.strip_prefix('='))
+}
+
+/// Builds a [`TimerSpec`] from the `TimersCalendar` (`a(sst)`) and `TimersMonotonic` (`a(stt)`)
+/// properties, which carry the same entries `systemctl show` prints.
+fn timer_spec(timers_calendar: &[(String,String,u64)], timers_monotonic: &[(String,u64,u64)]) -> TimerSpec {
+    TimerSpec {
+        calendar: timers_calendar.iter()
+            .filter(|(base, _, _)| base == "OnCalendar")
+            .map(|(_, spec, _)| Schedule::parse_calendar(spec))
+            .collect(),
+        monotonic: timers_monotonic.iter()
+            .filter_map(|(base, offset, _)| Some(MonotonicTrigger {
+                base: MonotonicBase::from_property(base)?,
+                offset: Duration::from_micros(*offset),
+            }))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn keys(properties: &[(&'static str, Value<'static>)]) -> Vec<&'static str> {
+        properties.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[test]
+    fn test_timer_properties() {
+        let unit_name = UnitName::new("test-dbus").unwrap();
+        let options = RegisterOptions {
+            persistent: true,
+            accuracy: Some(Duration::from_nanos(10)),
+            ..Default::default()
+        };
+        let properties = timer_properties(&Schedule::Every(Duration::from_secs(60)),unit_name,&options);
+        assert_eq!(keys(&properties),["Description","TimersMonotonic","Persistent","AccuracyUSec"]);
+        assert_eq!(properties[0].1,Value::from("systemd-wake test-dbus"));
+        assert_eq!(properties[1].1,Value::from(vec![("OnActiveSec".to_owned(),60_000_000u64),("OnUnitActiveSec".to_owned(),60_000_000u64)]));
+        assert_eq!(properties[3].1,Value::from(1u64));
+
+        let properties = timer_properties(&Schedule::Calendar("daily".to_owned()),unit_name,&RegisterOptions::default());
+        assert_eq!(properties[1],("TimersCalendar",Value::from(vec![("OnCalendar".to_owned(),"daily".to_owned())])));
+    }
+
+    #[test]
+    fn test_service_properties() {
+        let unit_name = UnitName::new("test-dbus").unwrap();
+        let options = RegisterOptions {
+            environment: vec![("PATH".to_owned(),"/opt/bin".to_owned())],
+            io_class: Some(IoClass::Idle),
+            slice: Some("myapp".to_owned()),
+            ..Default::default()
+        };
+        let properties = service_properties(unit_name,"payload","/opt/bin/systemd-wake",&options);
+        assert_eq!(keys(&properties),["Description","Environment","SyslogIdentifier","ExecStart","IOSchedulingClass","Slice"]);
+        assert_eq!(properties[1].1,Value::from(vec![format!("{}=payload",COMMAND_ENV_VAR),"PATH=/opt/bin".to_owned()]));
+        assert_eq!(properties[3].1,Value::from(vec![("/opt/bin/systemd-wake".to_owned(),vec!["/opt/bin/systemd-wake".to_owned()],false)]));
+        assert_eq!(properties[5].1,Value::from("myapp.slice"));
+    }
+
+    #[test]
+    fn test_check_supported() {
+        assert!(check_supported(&RegisterOptions::default()).is_ok());
+        let options = RegisterOptions {
+            extra_properties: vec!["Nice=5".to_owned()],
+            ..Default::default()
+        };
+        assert!(matches!(check_supported(&options),Err(RegistrationError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_query_parts() {
+        let environment = ["PATH=/usr/bin".to_owned(),format!("{}=payload",COMMAND_ENV_VAR)];
+        assert_eq!(command_from_environment(&environment),Some("payload"));
+        assert_eq!(command_from_environment(&environment[..1]),None);
+
+        let timer_spec = timer_spec(&[("OnCalendar".to_owned(),"2023-04-13 10:00:00".to_owned(),0)],&[("OnActiveUSec".to_owned(),1_800_000_000,0)]);
+        assert_eq!(timer_spec.waketime(),NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%F %T").ok());
+        assert_eq!(timer_spec.monotonic,[MonotonicTrigger { base: MonotonicBase::Active, offset: Duration::from_secs(1800) }]);
+    }
+}
@@ -0,0 +1,187 @@
+//! [`SystemdDuration`], for converting between [`Duration`]s and systemd's time span syntax.
+
+use std::fmt::{Display,Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use thiserror::Error;
+
+/// A [`Duration`] that formats and parses as a systemd time span (see `systemd.time(7)`), like
+/// the values of `AccuracySec=` or `OnActiveSec=`.
+///
+/// Formatting is precise down to the microsecond, which is as fine as systemd goes. Anything
+/// shorter than that but not zero becomes `1us` rather than `0`, which systemd often reads as
+/// "off" or "straight away".
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct SystemdDuration(Duration);
+
+impl SystemdDuration {
+    /// The wrapped duration.
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for SystemdDuration {
+    fn from(duration: Duration) -> Self {
+        SystemdDuration(duration)
+    }
+}
+
+/// systemd has no negative time spans, so negative durations become zero.
+impl From<chrono::Duration> for SystemdDuration {
+    fn from(duration: chrono::Duration) -> Self {
+        SystemdDuration(duration.to_std().unwrap_or_default())
+    }
+}
+
+impl From<SystemdDuration> for Duration {
+    fn from(duration: SystemdDuration) -> Self {
+        duration.0
+    }
+}
+
+/// Formats as e.g. `1h 30min 5s`.
+impl Display for SystemdDuration {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        format(self.0).fmt(f)
+    }
+}
+
+/// Accepts anything from `systemctl show` output, e.g. `1h 30min`, `50.401583s` or `2d`.
+impl FromStr for SystemdDuration {
+    type Err = ParseDurationError;
+
+    fn from_str(timespan: &str) -> Result<Self,Self::Err> {
+        parse(timespan).map(SystemdDuration).ok_or_else(|| ParseDurationError(timespan.to_owned()))
+    }
+}
+
+/// Error parsing a [`SystemdDuration`].
+#[derive(Error,Debug,Clone,PartialEq,Eq)]
+#[error("invalid systemd time span `{0}`")]
+pub struct ParseDurationError(String);
+
+/// Formats a duration using systemd's time span syntax, e.g. `1h 30min 5s`.
+fn format(duration: Duration) -> String {
+    const UNITS: [(&str, u128); 5] = [
+        ("h",3_600_000_000),
+        ("min",60_000_000),
+        ("s",1_000_000),
+        ("ms",1_000),
+        ("us",1),
+    ];
+    if duration.is_zero() {
+        return "0".to_owned();
+    }
+    let mut remaining = duration.as_micros().max(1);
+    let mut parts = Vec::new();
+    for (suffix, usec) in UNITS {
+        if remaining >= usec {
+            parts.push(format!("{}{}",remaining / usec,suffix));
+            remaining %= usec;
+        }
+    }
+    parts.join(" ")
+}
+
+/// Parses systemd's time span syntax, e.g. `1h 30min`, `50.401583s` or `2d`, as found in
+/// `systemctl show` output. Counts in whole microseconds, systemd's resolution, and gives up on
+/// spans too long to count that way.
+fn parse(timespan: &str) -> Option<Duration> {
+    let timespan = timespan.trim();
+    if timespan.is_empty() {
+        return None;
+    }
+    let mut total: u64 = 0;
+    let mut rest = timespan;
+    while !rest.is_empty() {
+        rest = rest.trim_start();
+        let whole_len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let whole = &rest[..whole_len];
+        rest = &rest[whole_len..];
+        let fraction = match rest.strip_prefix('.') {
+            Some(after_dot) => {
+                let fraction_len = after_dot.find(|c: char| !c.is_ascii_digit()).unwrap_or(after_dot.len());
+                rest = &after_dot[fraction_len..];
+                &after_dot[..fraction_len]
+            },
+            None => "",
+        };
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace()).unwrap_or(rest.len());
+        let unit_usec: u64 = match &rest[..unit_len] {
+            "us" | "usec" | "µs" => 1,
+            "ms" | "msec" => 1_000,
+            "" | "s" | "sec" | "second" | "seconds" => 1_000_000,
+            "m" | "min" | "minute" | "minutes" => 60_000_000,
+            "h" | "hr" | "hour" | "hours" => 3_600_000_000,
+            "d" | "day" | "days" => 86_400_000_000,
+            "w" | "week" | "weeks" => 604_800_000_000,
+            "M" | "month" | "months" => 2_629_800_000_000,
+            "y" | "year" | "years" => 31_557_600_000_000,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+
+        let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+        // anything past the 18th decimal is far below a microsecond for every unit
+        let fraction = &fraction[..fraction.len().min(18)];
+        let fraction_usec = if fraction.is_empty() {
+            0
+        } else {
+            (fraction.parse::<u128>().ok()? * unit_usec as u128 / 10u128.pow(fraction.len() as u32)) as u64
+        };
+        total = whole.checked_mul(unit_usec)
+            .and_then(|usec| usec.checked_add(fraction_usec))
+            .and_then(|usec| total.checked_add(usec))?;
+    }
+    Some(Duration::from_micros(total))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_timespan(timespan: &str) -> Option<Duration> {
+        timespan.parse::<SystemdDuration>().ok().map(Duration::from)
+    }
+
+    fn format_timespan(duration: Duration) -> String {
+        SystemdDuration::from(duration).to_string()
+    }
+
+    #[test]
+    fn test_format_timespan() {
+        assert_eq!(format_timespan(Duration::from_secs(1)),"1s");
+        assert_eq!(format_timespan(Duration::from_secs(5405)),"1h 30min 5s");
+        assert_eq!(format_timespan(Duration::from_millis(1500)),"1s 500ms");
+        assert_eq!(format_timespan(Duration::ZERO),"0");
+        assert_eq!(format_timespan(Duration::from_nanos(10)),"1us");
+        assert_eq!(format_timespan(Duration::from_nanos(500)),"1us");
+        assert_eq!(SystemdDuration::from(chrono::Duration::minutes(-5)).to_string(),"0");
+        assert_eq!(SystemdDuration::from(chrono::Duration::minutes(90)).to_string(),"1h 30min");
+    }
+
+    #[test]
+    fn test_parse_timespan() {
+        assert_eq!(parse_timespan("1h 30min 5s"),Some(Duration::from_secs(5405)));
+        assert_eq!(parse_timespan("1h30min"),Some(Duration::from_secs(5400)));
+        assert_eq!(parse_timespan("500ms"),Some(Duration::from_millis(500)));
+        assert_eq!(parse_timespan("90"),Some(Duration::from_secs(90)));
+        assert_eq!(parse_timespan(""),None);
+        assert_eq!(parse_timespan("infinity"),None);
+        assert_eq!("infinity".parse::<SystemdDuration>(),Err(ParseDurationError("infinity".to_owned())));
+        assert_eq!(parse_timespan("50.401583s"),Some(Duration::from_micros(50_401_583)));
+        assert_eq!(parse_timespan("1.5h"),Some(Duration::from_secs(5400)));
+        assert_eq!(parse_timespan("20µs"),Some(Duration::from_micros(20)));
+        assert_eq!(parse_timespan("."),None);
+        assert_eq!(parse_timespan("99999999999999999999999y"),None);
+        assert_eq!(parse_timespan("500000y 500000y"),None);
+        for duration in [Duration::from_secs(5405),Duration::from_millis(1500),Duration::ZERO] {
+            assert_eq!(parse_timespan(&format_timespan(duration)),Some(duration));
+        }
+    }
+}
@@ -8,12 +8,27 @@
 //! Requires the systemd-wake binary to be installed in order to work. Remember to install with
 //! `cargo install systemd-wake`.
 //!
-//! Use [`register()`] to schedule a command with systemd-run to wake at specificed time.
+//! Use [`register()`] to schedule a command with systemd-run to wake at specificed time, or on a
+//! repeating [`Schedule::Calendar`] expression.
 //!
 //! Use [`deregister()`] to cancel timer.
 //!
+//! Use [`list_registrations()`] to find every timer scheduled through this crate.
+//!
+//! By default everything runs against the user's service manager (`--user`). The `_scoped`
+//! variants take a [`Scope`] to target the system manager instead.
+//!
+//! `systemctl`, `systemd-run` and `loginctl` are looked up on `PATH`. Set the
+//! `SYSTEMD_WAKE_SYSTEMCTL`, `SYSTEMD_WAKE_SYSTEMD_RUN` and `SYSTEMD_WAKE_LOGINCTL` environment
+//! variables to run them from somewhere else, e.g. inside a container where they live outside the
+//! usual layout.
+//!
+//! systemd only exists on Linux. Elsewhere every call fails with
+//! [`CommandError::UnsupportedPlatform`], and [`systemd_available()`] tells ahead of time whether
+//! there's a systemd to talk to.
+//!
 //! ### Example
-//! ```
+//! ```no_run
 //! use systemd_wake::*;
 //!
 //! // one minute in the future
@@ -40,34 +55,157 @@
 
 /// Command serialization.
 pub mod command;
-use command::{CommandConfig,CommandConfigError};
+/// D-Bus backend, enabled with the `dbus` feature.
+#[cfg(feature = "dbus")]
+pub mod dbus;
+/// systemd time spans.
+pub mod duration;
+/// Async API, enabled with the `async` feature.
+#[cfg(feature = "async")]
+pub mod nonblocking;
+use command::{CommandConfig,CommandConfigError,Format};
+use duration::SystemdDuration;
+
+/// Environment variable the encoded command is handed to the systemd-wake helper in. It's stored
+/// on the transient service unit, which keeps the unit description free for humans.
+pub const COMMAND_ENV_VAR: &str = "SYSTEMD_WAKE_COMMAND";
 
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr,OsString};
 use std::fmt::{Display,Formatter};
-use std::process::{Command,Output};
+use std::path::{Path,PathBuf};
+use std::process::{Command,ExitStatus,Output};
+use std::time::{Duration,Instant};
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime,Local,NaiveDate,NaiveDateTime,NaiveTime,TimeZone,Timelike,Utc};
 use thiserror::Error;
 #[allow(unused_imports)]
-use tracing::{info,debug,warn,error,trace,Level};
+use tracing::{info,debug,warn,error,trace,instrument,Level};
 
 /// Wrapper struct for the name given to the systemd timer unit.
-#[derive(Copy,Clone)]
+///
+/// The only normalization is dropping a trailing `.timer` or `.service`; otherwise the name is
+/// stored exactly as given. [`UnitName::as_str()`], `as_ref()` and `Display` all return that
+/// stored name, and it's what systemd sees (`<name>.timer`, `<name>.service`), so it can be kept
+/// as a handle and passed back in later to find the same units.
+///
+/// Hashes and compares like the underlying `&str`, so it works as a map key that can be looked up
+/// with a plain string.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
 pub struct UnitName<'a> {
     name: &'a str,
 }
 
 impl<'a> UnitName<'a> {
-    /// Creates new TimerName and verifies that unit name meets constraints of being only
-    /// non-whitespace ASCII.
+    /// Longest accepted name. systemd caps full unit names at 256 characters, and this leaves
+    /// room for the `.service` suffix.
+    pub const MAX_LEN: usize = 256 - ".service".len();
+
+    /// Creates new TimerName and verifies that unit name meets systemd's naming rules: non-empty,
+    /// at most [`UnitName::MAX_LEN`] characters, and only ASCII letters, digits, and `:_.-\`.
+    ///
+    /// A trailing `.timer` or `.service` is dropped, since the crate adds those itself, so
+    /// `foo.timer` names the same timer as `foo`.
     pub fn new(name: &'a str) -> Result<Self,UnitNameError> {
+        let name = strip_unit_suffix(name);
+        if name.is_empty() {
+            return Err(UnitNameError::Empty);
+        }
         if !name.is_ascii() {
             return Err(UnitNameError::NotAscii);
         }
         if name.contains(char::is_whitespace) {
             return Err(UnitNameError::ContainsWhitespace);
         }
+        if let Some(c) = name.chars().find(|c| !is_unit_name_char(*c)) {
+            return Err(UnitNameError::InvalidCharacter(c));
+        }
+        if name.len() > Self::MAX_LEN {
+            return Err(UnitNameError::TooLong);
+        }
         Ok(Self { name })
     }
+
+    /// The name as stored, after dropping any unit suffix. Borrows from the string given to
+    /// [`UnitName::new()`], not from `self`.
+    pub fn as_str(&self) -> &'a str {
+        self.name
+    }
+
+    /// Full name of the timer unit, `<name>.timer`.
+    pub fn timer_unit(&self) -> String {
+        format!("{}.timer",self.name)
+    }
+
+    /// Full name of the service unit the timer starts, `<name>.service`.
+    pub fn service_unit(&self) -> String {
+        format!("{}.service",self.name)
+    }
+
+    /// Turns arbitrary text, like a reminder title, into a valid unit name by replacing every
+    /// character [`UnitName::new()`] would reject with `-` and truncating to
+    /// [`UnitName::MAX_LEN`]. Empty input becomes `unnamed`.
+    ///
+    /// Different inputs can sanitize to the same name (`a b` and `a/b` both give `a-b`), so
+    /// check for an existing timer before relying on the result being unique.
+    pub fn sanitize(input: &str) -> UnitNameBuf {
+        let mut name: String = input.chars()
+            .map(|c| if is_unit_name_char(c) { c } else { '-' })
+            .take(Self::MAX_LEN)
+            .collect();
+        name.truncate(strip_unit_suffix(&name).len());
+        if name.is_empty() {
+            name.push_str("unnamed");
+        }
+        UnitNameBuf { name }
+    }
+
+    /// Makes up a unique name for a timer running `command` at `time`, for fire-and-forget use
+    /// where the name doesn't matter. It's `<program>-<time>-<hash>`, with the hash covering the
+    /// command, the time and a random nonce, so calling it twice with the same arguments still
+    /// gives two different names.
+    ///
+    /// Since the name can't be worked out again, store it if the timer will need to be queried
+    /// or deregistered later.
+    pub fn generate(command: &Command, time: &NaiveDateTime) -> UnitNameBuf {
+        use std::hash::{BuildHasher,Hash,Hasher};
+        use std::sync::atomic::{AtomicU64,Ordering};
+
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        // RandomState's keys are seeded from the OS's randomness, which serves as the nonce, and
+        // the counter keeps calls within the process apart
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        command.get_program().hash(&mut hasher);
+        command.get_args().for_each(|arg| arg.hash(&mut hasher));
+        command.get_envs().for_each(|env| env.hash(&mut hasher));
+        command.get_current_dir().hash(&mut hasher);
+        time.hash(&mut hasher);
+        std::process::id().hash(&mut hasher);
+        COUNTER.fetch_add(1,Ordering::Relaxed).hash(&mut hasher);
+
+        let program = Path::new(command.get_program()).file_name().unwrap_or_default().to_string_lossy();
+        let program: String = program.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '-' })
+            .take(64)
+            .collect();
+        let program = program.trim_matches('-');
+        let program = if program.is_empty() { "command" } else { program };
+        UnitNameBuf {
+            name: format!("{}-{}-{:016x}",program,time.format("%Y%m%dT%H%M%S"),hasher.finish()),
+        }
+    }
+}
+
+fn strip_unit_suffix(name: &str) -> &str {
+    name.strip_suffix(".timer")
+        .or_else(|| name.strip_suffix(".service"))
+        .unwrap_or(name)
+}
+
+fn is_unit_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '.' | '-' | '\\')
 }
 
 impl AsRef<str> for UnitName<'_> {
@@ -76,12 +214,192 @@ impl AsRef<str> for UnitName<'_> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for UnitName<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok,S::Error> {
+        serializer.serialize_str(self.name)
+    }
+}
+
+/// Borrows from the input, so only works with formats that can hand out a `&str`, like JSON
+/// without escapes. Deserialize a [`UnitNameBuf`] otherwise.
+#[cfg(feature = "serde")]
+impl<'de: 'a, 'a> serde::Deserialize<'de> for UnitName<'a> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self,D::Error> {
+        let name = <&'de str>::deserialize(deserializer)?;
+        UnitName::new(name).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Borrow<str> for UnitName<'_> {
+    fn borrow(&self) -> &str {
+        self.name
+    }
+}
+
+impl<'a> From<&UnitName<'a>> for UnitName<'a> {
+    fn from(name: &UnitName<'a>) -> Self {
+        *name
+    }
+}
+
 impl Display for UnitName<'_> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         self.name.fmt(f)
     }
 }
 
+/// Owned version of [`UnitName`], for names built at runtime or stored alongside other data.
+///
+/// Every function taking a unit name accepts either form; pass a `&UnitNameBuf` where a
+/// [`UnitName`] is expected. Like [`UnitName`], it can be used as a map key and looked up with a
+/// `&str`.
+///
+/// With the `serde` feature it (de)serializes as a plain string, running the same validation as
+/// [`UnitNameBuf::new()`] when deserializing.
+#[derive(Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize,serde::Deserialize), serde(try_from = "String", into = "String"))]
+pub struct UnitNameBuf {
+    name: String,
+}
+
+impl UnitNameBuf {
+    /// Creates new UnitNameBuf, applying the same validation as [`UnitName::new()`].
+    pub fn new(name: impl Into<String>) -> Result<Self,UnitNameError> {
+        let mut name = name.into();
+        let len = UnitName::new(&name)?.name.len();
+        name.truncate(len);
+        Ok(Self { name })
+    }
+
+    /// The name as stored, see [`UnitName::as_str()`].
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// Borrows as a [`UnitName`].
+    pub fn as_unit_name(&self) -> UnitName<'_> {
+        UnitName { name: &self.name }
+    }
+}
+
+impl<'a> From<&'a UnitNameBuf> for UnitName<'a> {
+    fn from(name: &'a UnitNameBuf) -> Self {
+        name.as_unit_name()
+    }
+}
+
+impl From<UnitName<'_>> for UnitNameBuf {
+    fn from(name: UnitName<'_>) -> Self {
+        Self { name: name.name.to_owned() }
+    }
+}
+
+impl TryFrom<String> for UnitNameBuf {
+    type Error = UnitNameError;
+
+    fn try_from(name: String) -> Result<Self,Self::Error> {
+        Self::new(name)
+    }
+}
+
+impl From<UnitNameBuf> for String {
+    fn from(name: UnitNameBuf) -> Self {
+        name.name
+    }
+}
+
+impl From<&UnitName<'_>> for UnitNameBuf {
+    fn from(name: &UnitName<'_>) -> Self {
+        (*name).into()
+    }
+}
+
+impl Borrow<str> for UnitNameBuf {
+    fn borrow(&self) -> &str {
+        &self.name
+    }
+}
+
+impl AsRef<str> for UnitNameBuf {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for UnitNameBuf {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+/// Prefix that keeps one app's unit names apart from everyone else's on the same service
+/// manager. [`Namespace::name()`] turns `name` into `<prefix>-name`.
+///
+/// ```no_run
+/// # use systemd_wake::*;
+/// let namespace = Namespace::new("myapp").unwrap();
+/// let unit_name = namespace.name("reminder-42").unwrap();
+/// assert_eq!(unit_name.as_ref(),"myapp-reminder-42");
+/// // clean up everything the app scheduled
+/// namespace.deregister_all().unwrap();
+/// ```
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct Namespace {
+    prefix: String,
+}
+
+impl Namespace {
+    /// Creates a namespace, checking `prefix` against the same rules as [`UnitName::new()`] and
+    /// leaving room for at least one character of name after it.
+    pub fn new(prefix: impl Into<String>) -> Result<Self,UnitNameError> {
+        let prefix = prefix.into();
+        UnitName::new(&prefix)?;
+        if prefix.len() + 2 > UnitName::MAX_LEN {
+            return Err(UnitNameError::TooLong);
+        }
+        Ok(Self { prefix })
+    }
+
+    /// The prefix, without the `-` separator.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Builds `<prefix>-<name>`, validating the combined name.
+    pub fn name(&self, name: &str) -> Result<UnitNameBuf,UnitNameError> {
+        UnitNameBuf::new(format!("{}-{}",self.prefix,name))
+    }
+
+    /// The part of `unit_name` after the prefix, if it belongs to this namespace.
+    pub fn strip<'n>(&self, unit_name: &'n str) -> Option<&'n str> {
+        unit_name.strip_prefix(self.prefix.as_str())?.strip_prefix('-')
+    }
+
+    /// [`list_registrations()`], keeping only the timers in this namespace.
+    pub fn list(&self) -> Result<Vec<TimerInfo>,QueryError> {
+        self.list_scoped(Scope::User)
+    }
+
+    /// Same as [`Namespace::list()`], but against the service manager selected by `scope`.
+    pub fn list_scoped(&self, scope: Scope) -> Result<Vec<TimerInfo>,QueryError> {
+        Ok(list_registrations_scoped(scope)?.into_iter()
+            .filter(|timer| self.strip(timer.unit_name.as_ref()).is_some())
+            .collect())
+    }
+
+    /// [`deregister_prefix()`] for every timer in this namespace.
+    pub fn deregister_all(&self) -> Result<PrefixDeregistration,QueryError> {
+        self.deregister_all_scoped(Scope::User)
+    }
+
+    /// Same as [`Namespace::deregister_all()`], but against the service manager selected by
+    /// `scope`.
+    pub fn deregister_all_scoped(&self, scope: Scope) -> Result<PrefixDeregistration,QueryError> {
+        deregister_prefix_scoped(&format!("{}-",self.prefix),scope)
+    }
+}
+
 /// Error struct for creating [`UnitName`].
 #[derive(Error,Debug)]
 #[allow(missing_docs)]
@@ -90,6 +408,12 @@ pub enum UnitNameError {
     NotAscii,
     #[error("UnitName cannot conatin whitespace")]
     ContainsWhitespace,
+    #[error("UnitName cannot be empty")]
+    Empty,
+    #[error("UnitName cannot contain {0:?}")]
+    InvalidCharacter(char),
+    #[error("UnitName cannot be longer than {} characters", UnitName::MAX_LEN)]
+    TooLong,
 }
 
 /// Error struct for registration.
@@ -100,199 +424,3668 @@ pub enum RegistrationError {
     Query(#[from] QueryError),
     #[error("unit name is already in use")]
     Duplicate,
-    #[error("error with registration command")]
+    #[error("unit name is taken by {0}, which isn't a systemd-wake timer")]
+    NameConflict(String),
+    #[error("error with registration command{}", command_reason(.0))]
     Command(#[from] CommandError),
+    #[error("no schedule given for the timer")]
+    NoSchedule,
+    #[error("error encoding command")]
+    CommandConfig(#[from] CommandConfigError),
+    #[error("wake time {0} is in the past")]
+    TimeInPast(NaiveDateTime),
+    #[error("program `{}` not found", .0.to_string_lossy())]
+    ProgramNotFound(OsString),
+    #[error("invalid option: {0}")]
+    InvalidOption(String),
+    #[error("lingering is off, so the user timer won't fire while the user is logged out (see `loginctl enable-linger`)")]
+    NotLingering,
+    #[error("the timer's schedule can't be recreated")]
+    NotRecreatable,
+    #[error("timer didn't show up within {} of registering it", SystemdDuration::from(*.0))]
+    NeverLoaded(Duration),
+    #[error("old timer was stopped but the replacement failed to register ({})", if *.restored { "old timer restored" } else { "old timer lost" })]
+    UpdateFailed {
+        #[source]
+        source: Box<RegistrationError>,
+        restored: bool,
+    },
 }
 
-/// Calls systemd-run to register command to wake at specified time using provided name.
-pub fn register(event_time: NaiveDateTime, unit_name: UnitName, command: Command) -> Result<(),RegistrationError> {
-    debug!("registering timer");
+/// What a failed systemd-run or systemctl wrote to standard error, which is where systemd explains
+/// why, e.g. "Unit name invalid".
+fn command_reason(error: &CommandError) -> String {
+    match error {
+        CommandError::CommandFailed { stderr, .. } if !stderr.trim().is_empty() => format!(": {}",stderr.trim()),
+        _ => String::new(),
+    }
+}
 
-    if check_loaded(unit_name)? {
-        return Err(RegistrationError::Duplicate);
+/// Which systemd service manager to talk to.
+///
+/// Defaults to [`Scope::User`], which is what the unscoped functions use.
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq)]
+pub enum Scope {
+    /// The calling user's service manager (`--user`).
+    #[default]
+    User,
+    /// The system service manager (`--system`). Usually requires root.
+    System,
+}
+
+impl Scope {
+    fn flag(&self) -> &'static str {
+        match self {
+            Scope::User => "--user",
+            Scope::System => "--system",
+        }
     }
+}
 
-    let unit_name = format!("--unit={}",unit_name);
+/// Runs the `systemctl` and `systemd-run` commands built by this crate. Swapped out in tests so
+/// the exact invocations can be checked without a live systemd.
+pub(crate) trait CommandRunner {
+    fn run(&self, command: Command) -> Result<Output,CommandError>;
+}
 
-    let on_calendar = event_time.format("--on-calendar=%F %T").to_string();
-    debug!("timer set for {}",on_calendar);
+/// Runs commands for real with [`run_command()`].
+struct ProcessRunner;
 
-    let encoded_command = CommandConfig::encode(command).unwrap();
+impl CommandRunner for ProcessRunner {
+    fn run(&self, command: Command) -> Result<Output,CommandError> {
+        check_platform()?;
+        run_command(command)
+    }
+}
 
-    let mut systemd_command = Command::new("systemd-run");
-    systemd_command
-        .arg("--user")
-        .arg(unit_name)
-        .arg(on_calendar)
-        .arg("systemd-wake")
-        .arg(encoded_command);
+/// Fails straight away on platforms without systemd, rather than with a confusing "not found"
+/// from trying to run systemctl.
+fn check_platform() -> Result<(),CommandError> {
+    if cfg!(target_os = "linux") {
+        Ok(())
+    } else {
+        Err(CommandError::UnsupportedPlatform(std::env::consts::OS))
+    }
+}
 
-    debug!("running timer command: {:?}",systemd_command);
-    run_command(systemd_command)?;
-    Ok(())
+/// Whether the machine was booted with systemd, so there's a service manager to register timers
+/// with. Always `false` off Linux. Handy for shared code that should skip scheduling where it
+/// can't work instead of failing.
+///
+/// This is the same check as `sd_booted(3)`. It doesn't say whether the calling user has a
+/// running user manager, which isn't the case in e.g. a plain `su` session.
+pub fn systemd_available() -> bool {
+    cfg!(target_os = "linux") && Path::new("/run/systemd/system").is_dir()
 }
 
-/// Calls systemctl to deregister specified timer.
-pub fn deregister(unit_name: UnitName) -> Result<(Command,NaiveDateTime),RegistrationError> {
-    let (command, deadline) = query_registration(unit_name)?;
+/// Which service manager to talk to and how to run commands against it.
+#[derive(Copy,Clone)]
+struct Systemd<'r> {
+    scope: Scope,
+    runner: &'r dyn CommandRunner,
+}
 
-    debug!("deregistering timer");
+impl Systemd<'static> {
+    fn new(scope: Scope) -> Self {
+        Systemd {
+            scope,
+            runner: &ProcessRunner,
+        }
+    }
+}
 
-    let timer_name = {
-        let mut name = unit_name.to_string();
-        name.push_str(".timer");
-        name
-    };
+impl Systemd<'_> {
+    fn systemd_run(&self) -> Command {
+        self.command(std::env::var_os("SYSTEMD_WAKE_SYSTEMD_RUN").unwrap_or_else(|| "systemd-run".into()))
+    }
 
-    let mut systemd_command = Command::new("systemctl");
-    systemd_command
-        .arg("--user")
-        .arg("stop")
-        .arg(timer_name);
+    fn systemctl(&self) -> Command {
+        self.command(std::env::var_os("SYSTEMD_WAKE_SYSTEMCTL").unwrap_or_else(|| "systemctl".into()))
+    }
 
-    debug!("running stop timer command: {:?}",systemd_command);
-    run_command(systemd_command)?;
-    Ok((command,deadline))
+    /// Unlike the other two, loginctl doesn't take a scope.
+    fn loginctl(&self) -> Command {
+        let mut command = Command::new(std::env::var_os("SYSTEMD_WAKE_LOGINCTL").unwrap_or_else(|| "loginctl".into()));
+        command.env("LC_ALL","C");
+        command
+    }
+
+    fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let mut command = Command::new(program);
+        // output is parsed, so keep it from being translated or localized
+        command.arg(self.scope.flag()).env("LC_ALL","C");
+        command
+    }
+
+    fn run(&self, command: Command) -> Result<Output,CommandError> {
+        self.runner.run(command)
+    }
 }
 
-/// Convenience function for changing scheduled waketime
-pub fn reschedule(unit_name: UnitName, waketime: NaiveDateTime) -> Result<(),RegistrationError> {
-    let (command, _) = deregister(unit_name)?;
-    register(waketime,unit_name,command)
+/// When a timer should fire.
+///
+/// A plain [`NaiveDateTime`] converts into [`Schedule::At`], so existing callers can keep passing
+/// one straight to [`register()`]. Naive times are interpreted by systemd in the system timezone,
+/// so only pass one built from local time (like `Local::now().naive_local()`). A timezone-aware
+/// [`DateTime`] converts into an explicit UTC calendar entry instead, which fires at the right
+/// moment whatever the system timezone is.
+#[derive(Clone,Debug,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum Schedule {
+    /// Fire once at the given local time.
+    At(NaiveDateTime),
+    /// Raw systemd calendar expression such as `daily`, `Mon,Tue *-*-* 08:00:00` or `*:0/15`. See
+    /// `systemd.time(7)` for the syntax. Note that systemd normalizes these, so querying the timer
+    /// later may hand back a different (but equivalent) string.
+    Calendar(String),
+    /// Fire once, this long after registering (`OnActiveSec=`). Uses the monotonic clock, so it
+    /// isn't thrown off by changes to the wall clock.
+    After(Duration),
+    /// Fire this long after the machine booted (`OnBootSec=`). If that point has already passed
+    /// the timer fires straight away. systemd-run units are transient and don't survive a
+    /// reboot, so this can't by itself make something run after every boot.
+    OnBoot(Duration),
+    /// Fire this long after the service manager started (`OnStartupSec=`). For the user manager
+    /// that's when the user's first session began. Same caveats as [`Schedule::OnBoot`].
+    OnStartup(Duration),
+    /// Fire repeatedly, this long after the command last started (`OnUnitActiveSec=`), the usual
+    /// way to run something "every 6 hours". The first run is this long after registering
+    /// (`OnActiveSec=`), since a command that never ran has nothing to count from.
+    Every(Duration),
 }
 
-fn extract_property(unit_name: UnitName, property: &str) -> Result<String,QueryError> {
-    let unit_name = {
-        let mut name = unit_name.to_string();
-        name.push_str(".timer");
-        name
-    };
+impl Schedule {
+    fn timer_args(&self) -> Vec<String> {
+        match self {
+            Schedule::At(event_time) => vec![format!("--on-calendar={}",format_calendar_time(event_time))],
+            Schedule::Calendar(calendar) => vec![format!("--on-calendar={}",calendar)],
+            Schedule::After(offset) => vec![format!("--on-active={}",SystemdDuration::from(*offset))],
+            Schedule::OnBoot(offset) => vec![format!("--on-boot={}",SystemdDuration::from(*offset))],
+            Schedule::OnStartup(offset) => vec![format!("--on-startup={}",SystemdDuration::from(*offset))],
+            Schedule::Every(interval) => {
+                let interval = SystemdDuration::from(*interval);
+                vec![format!("--on-active={}",interval),format!("--on-unit-active={}",interval)]
+            },
+        }
+    }
 
-    let mut systemd_command = Command::new("systemctl");
-    systemd_command
-        .arg("--user")
-        .arg("show")
-        .arg(unit_name)
-        .arg(format!("--property={}",property));
+    /// The single wall-clock time this schedule fires at, if it's a one-off.
+    fn waketime(&self) -> Option<NaiveDateTime> {
+        match self {
+            Schedule::At(waketime) => Some(*waketime),
+            Schedule::Calendar(calendar) => match Schedule::parse_calendar(calendar) {
+                Schedule::At(waketime) => Some(waketime),
+                _ => None,
+            },
+            Schedule::After(_) | Schedule::OnBoot(_) | Schedule::OnStartup(_) | Schedule::Every(_) => None,
+        }
+    }
 
-    let output = run_command(systemd_command)?;
+    /// Fires every week on `weekday` at `time` (local), e.g. `Mon *-*-* 09:00:00`.
+    pub fn weekly(weekday: chrono::Weekday, time: chrono::NaiveTime) -> Self {
+        Schedule::Calendar(format!("{} *-*-* {}",weekday,time.format("%T")))
+    }
 
-    match String::from_utf8(output.stdout) {
-        Ok(string) => {
-            if let Some(value) = string.strip_prefix(&format!("{}=",property)) {
-                return Ok(value.trim_end().to_owned())
-            } else {
-                return Err(QueryError::ParseError);
-            }
-        },
-        Err(_) => return Err(QueryError::ParseError),
+    /// Builds a [`Schedule::Calendar`] expression by formatting `event_time` with a chrono format
+    /// string, for when the fixed `%F %T` of [`Schedule::At`] isn't what's wanted. For example
+    /// `%a *-*-* %H:%M:00` repeats every week on the same weekday and time, and `*-*-%d %T`
+    /// every month on the same day. The result has to be a valid `systemd.time(7)` expression.
+    pub fn formatted(event_time: NaiveDateTime, format: &str) -> Self {
+        Schedule::Calendar(event_time.format(format).to_string())
+    }
+
+    fn parse_calendar(calendar: &str) -> Self {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(calendar,"%Y-%m-%d %H:%M:%S%.f") {
+            return Schedule::At(datetime);
+        }
+        if let Ok(utc) = NaiveDateTime::parse_from_str(calendar,"%Y-%m-%d %H:%M:%S%.f UTC") {
+            return Schedule::At(Local.from_utc_datetime(&utc).naive_local());
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(calendar,"%Y-%m-%d") {
+            return Schedule::At(date.and_time(NaiveTime::MIN));
+        }
+        Schedule::Calendar(calendar.to_owned())
     }
 }
 
-fn check_loaded(unit_name: UnitName) -> Result<bool,QueryError> {
-    Ok(extract_property(unit_name,"LoadState")? == "loaded")
+/// Formats a one-off wake time for `OnCalendar=`, keeping any fraction of a second down to the
+/// microsecond (systemd's resolution).
+fn format_calendar_time(event_time: &NaiveDateTime) -> String {
+    if event_time.nanosecond() / 1_000 == 0 {
+        event_time.format("%F %T").to_string()
+    } else {
+        event_time.format("%F %T%.6f").to_string()
+    }
 }
 
-/// Returns registered command and wake up time for unit if it exists.
-pub fn query_registration(unit_name: UnitName) -> Result<(Command,NaiveDateTime),QueryError> {
-    debug!("querying registration");
-    // look for:
-    // LoadState
-    // Description
-    // TimersCalendar
+/// What a monotonic timer entry counts from.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum MonotonicBase {
+    /// When the timer was started (`OnActiveSec=`).
+    Active,
+    /// When the machine booted (`OnBootSec=`).
+    Boot,
+    /// When the service manager started (`OnStartupSec=`).
+    Startup,
+    /// When the triggered unit was last activated (`OnUnitActiveSec=`).
+    UnitActive,
+    /// When the triggered unit was last deactivated (`OnUnitInactiveSec=`).
+    UnitInactive,
+}
 
-    if !check_loaded(unit_name)? {
-        return Err(QueryError::NotLoaded);
+impl MonotonicBase {
+    fn from_property(name: &str) -> Option<Self> {
+        match name {
+            "OnActiveUSec" => Some(MonotonicBase::Active),
+            "OnBootUSec" => Some(MonotonicBase::Boot),
+            "OnStartupUSec" => Some(MonotonicBase::Startup),
+            "OnUnitActiveUSec" => Some(MonotonicBase::UnitActive),
+            "OnUnitInactiveUSec" => Some(MonotonicBase::UnitInactive),
+            _ => None,
+        }
     }
+}
 
-    let desc = extract_property(unit_name, "Description")?;
-    let command = if let Some(splits) = desc.split_once(" ") {
-        CommandConfig::decode(splits.1)?
-    } else {
-        return Err(QueryError::ParseError);
-    };
+/// One monotonic timer entry: fire `offset` after `base`.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct MonotonicTrigger {
+    /// What the offset counts from.
+    pub base: MonotonicBase,
+    /// How long after `base` the timer fires.
+    pub offset: Duration,
+}
 
-    let calendar = extract_property(unit_name, "TimersCalendar")?;
-    let datetime_str = calendar
-        .split_once("OnCalendar=").ok_or(QueryError::ParseError)?.1
-        .split_once(" ;").ok_or(QueryError::ParseError)?.0;
+/// Timer settings as reported back by systemd.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct TimerSpec {
+    /// One entry per `OnCalendar=` setting on the timer, in the order systemd lists them.
+    pub calendar: Vec<Schedule>,
+    /// One entry per monotonic setting (`OnActiveSec=` and friends) on the timer.
+    pub monotonic: Vec<MonotonicTrigger>,
+}
 
-    let datetime = match chrono::NaiveDateTime::parse_from_str(&datetime_str,"%Y-%m-%d %H:%M:%S") {
-        Ok(x) => x,
-        Err(_) => return Err(QueryError::ParseError),
-    };
+impl TimerSpec {
+    /// The wake time of a timer consisting of a single one-off calendar entry, which is what
+    /// [`register()`] creates from a [`NaiveDateTime`].
+    pub fn waketime(&self) -> Option<NaiveDateTime> {
+        match self.calendar.as_slice() {
+            [Schedule::At(waketime)] => Some(*waketime),
+            _ => None,
+        }
+    }
 
-    Ok((command,datetime))
+    /// A schedule that recreates this timer, if it's simple enough to have one. Timers counting
+    /// from activation can't be recreated since the count would start over.
+    fn schedule(&self) -> Option<Schedule> {
+        match (self.calendar.as_slice(), self.monotonic.as_slice()) {
+            ([schedule], []) => Some(schedule.clone()),
+            ([], [trigger]) => match trigger.base {
+                MonotonicBase::Boot => Some(Schedule::OnBoot(trigger.offset)),
+                MonotonicBase::Startup => Some(Schedule::OnStartup(trigger.offset)),
+                _ => None,
+            },
+            // the interval restarting is harmless for a periodic timer
+            ([], [first, second]) if first.offset == second.offset => match (first.base, second.base) {
+                (MonotonicBase::Active, MonotonicBase::UnitActive) | (MonotonicBase::UnitActive, MonotonicBase::Active) => Some(Schedule::Every(first.offset)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn parse(timers_calendar: &str, timers_monotonic: &str) -> Self {
+        TimerSpec {
+            calendar: Self::parse_calendar(timers_calendar),
+            monotonic: Self::parse_monotonic(timers_monotonic),
+        }
+    }
+
+    /// Parses systemd's `TimersCalendar` property, which looks like
+    /// `{ OnCalendar=2023-04-13 10:00:00 ; next_elapse=Thu 2023-04-13 10:00:00 CEST }` and may
+    /// repeat once per entry.
+    fn parse_calendar(timers_calendar: &str) -> Vec<Schedule> {
+        timers_calendar
+            .split("OnCalendar=")
+            .skip(1)
+            .filter_map(|entry| {
+                let spec = entry.split([';','\n']).next()?.trim().trim_end_matches('}').trim_end();
+                (!spec.is_empty()).then(|| Schedule::parse_calendar(spec))
+            })
+            .collect()
+    }
 
+    /// Parses systemd's `TimersMonotonic` property, which looks like
+    /// `{ OnActiveUSec=30min ; next_elapse=1h 2min }` and may repeat once per entry.
+    fn parse_monotonic(timers_monotonic: &str) -> Vec<MonotonicTrigger> {
+        timers_monotonic
+            .split('{')
+            .skip(1)
+            .filter_map(|entry| {
+                let (base, offset) = entry.split([';','}']).next()?.trim().split_once('=')?;
+                Some(MonotonicTrigger {
+                    base: MonotonicBase::from_property(base)?,
+                    offset: offset.parse::<SystemdDuration>().ok()?.into(),
+                })
+            })
+            .collect()
+    }
 }
 
-/// Error struct for querying task registration.
-#[derive(Error,Debug)]
-pub enum QueryError {
-    /// Error sending command to systemd
-    #[error("systemd command error")]
-    Command(#[from] CommandError),
-    /// Provided unit name is not loaded
-    #[error("unit with provided name not loaded")]
-    NotLoaded,
-    /// Error parsing systemd output
-    #[error("error parsing systemd output")]
-    ParseError,
-    /// Error decoding command
-    #[error("error decoding command")]
-    DecodeError(#[from] CommandConfigError),
+impl From<NaiveDateTime> for Schedule {
+    fn from(event_time: NaiveDateTime) -> Self {
+        Schedule::At(event_time)
+    }
 }
 
-/// Error struct for running a command. Wraps running with a non-success exit status as an error variant.
-#[derive(Error,Debug)]
-pub enum CommandError {
-    /// Error running the command
-    #[error("error running command")]
-    RunCommand(#[from] std::io::Error),
-    /// Command ran, but exited with failure status
-    #[error("command exited with failure status")]
-    CommandFailed(Output),
+/// Fires once at midnight (local) at the start of the day, as a bare `%F` calendar entry.
+impl From<NaiveDate> for Schedule {
+    fn from(date: NaiveDate) -> Self {
+        Schedule::Calendar(date.format("%F").to_string())
+    }
 }
 
-/// Helper function for running commands.
-pub fn run_command(mut command: Command) -> Result<Output,CommandError> {
-    match command.output() {
-        Ok(output) => {
-            if output.status.success() {
-                Ok(output)
-            } else {
-                Err(CommandError::CommandFailed(output))
+impl<Tz: TimeZone> From<DateTime<Tz>> for Schedule {
+    fn from(event_time: DateTime<Tz>) -> Self {
+        Schedule::Calendar(event_time.with_timezone(&Utc).format("%F %T UTC").to_string())
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Schedule::At(event_time) => format_calendar_time(event_time).fmt(f),
+            Schedule::Calendar(calendar) => calendar.fmt(f),
+            Schedule::After(offset) => write!(f,"{} after registration",SystemdDuration::from(*offset)),
+            Schedule::OnBoot(offset) => write!(f,"{} after boot",SystemdDuration::from(*offset)),
+            Schedule::OnStartup(offset) => write!(f,"{} after startup",SystemdDuration::from(*offset)),
+            Schedule::Every(interval) => write!(f,"every {}",SystemdDuration::from(*interval)),
+        }
+    }
+}
+
+/// Units created by a successful [`register()`].
+#[derive(Clone,Debug,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct Registration {
+    /// Unit name the timer was registered under.
+    pub unit_name: UnitNameBuf,
+    /// Timer unit systemd-run created, e.g. `my-unit.timer`.
+    pub timer_unit: String,
+    /// Service unit the timer will start, e.g. `my-unit.service`.
+    pub service_unit: String,
+    /// Schedule the timer was registered with.
+    pub schedule: Schedule,
+    /// When systemd actually plans to fire the timer, read back straight after registering. This
+    /// can differ from the requested time, e.g. with a randomized delay. `None` if systemd
+    /// couldn't say, which includes timers on a monotonic schedule.
+    pub next_elapse: Option<NaiveDateTime>,
+    /// Invocation ID of the timer unit, as printed by systemd-run. Journal entries about the
+    /// timer carry it as `_SYSTEMD_INVOCATION_ID`. Only newer versions of systemd print it.
+    pub invocation_id: Option<String>,
+}
+
+impl Registration {
+    fn from_output(unit_name: UnitName, schedule: Schedule, output: &Output) -> Self {
+        let mut timer_unit = None;
+        let mut service_unit = None;
+        // systemd-run reports these on stderr, but check both streams to be safe
+        let text = format!("{}\n{}",String::from_utf8_lossy(&output.stderr),String::from_utf8_lossy(&output.stdout));
+        let mut invocation_id = None;
+        for line in text.lines() {
+            // newer versions add e.g. "; invocation ID: 6c7b3d0a..." after the unit
+            let mut fields = line.trim().split(';').map(str::trim);
+            let Some(first) = fields.next() else { continue };
+            if let Some(unit) = first.strip_prefix("Running timer as unit: ") {
+                timer_unit = Some(unit.to_owned());
+                invocation_id = fields.find_map(|field| field.strip_prefix("invocation ID: ")).map(str::to_owned);
+            } else if let Some(unit) = first.strip_prefix("Will run service as unit: ") {
+                service_unit = Some(unit.to_owned());
             }
-        },
-        Err(e) => {
-            Err(CommandError::RunCommand(e))
+        }
+        Registration {
+            unit_name: unit_name.into(),
+            timer_unit: timer_unit.unwrap_or_else(|| unit_name.timer_unit()),
+            service_unit: service_unit.unwrap_or_else(|| unit_name.service_unit()),
+            schedule,
+            next_elapse: None,
+            invocation_id,
         }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Calls systemd-run to register command to wake at specified time using provided name.
+///
+/// Accepts anything convertible into a [`Schedule`], so a [`NaiveDateTime`] gives a one-off wake
+/// and a [`Schedule::Calendar`] expression gives a repeating timer.
+///
+/// The command can also be a [`CommandConfig`] for things [`Command`] can't express, like stdio
+/// redirection.
+///
+/// Use [`register_verbose()`] to also get what systemd-run printed.
+pub fn register<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<Registration,RegistrationError> {
+    register_scoped(schedule,unit_name,command,Scope::User)
+}
 
-    #[test]
-    fn test_beep() {
-        // one minute in the future
-        let waketime = chrono::Local::now().naive_local() + chrono::Duration::minutes(1);
+/// Registers command to run once at midnight (local) at the start of `date`, for "remind me on
+/// this day". Like any one-off, today's date is already in the past and gets
+/// [`RegistrationError::TimeInPast`].
+pub fn register_on_date<'a>(date: NaiveDate, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<Registration,RegistrationError> {
+    register(date,unit_name,command)
+}
 
-        // schedule a short beep
-        let mut command = std::process::Command::new("play");
-        command.args(vec!["-q","-n","synth","0.1","sin","880"]);
+/// Registers command to run `offset` after `base`, e.g. to snooze something for ten minutes from
+/// when it went off. Returns the wake time it worked out, in local time, for showing or storing.
+pub fn register_at_offset<'a, Tz: TimeZone>(base: DateTime<Tz>, offset: Duration, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<NaiveDateTime,RegistrationError> {
+    let waketime = offset_waketime(base,offset)?;
+    register(waketime,unit_name,command)?;
+    Ok(waketime)
+}
 
-        // create unit handle
-        let unit_name = UnitName::new("my-special-unit-name-123").unwrap();
+fn offset_waketime<Tz: TimeZone>(base: DateTime<Tz>, offset: Duration) -> Result<NaiveDateTime,RegistrationError> {
+    let base = base.with_timezone(&Local).naive_local();
+    chrono::Duration::from_std(offset).ok()
+        .and_then(|offset| base.checked_add_signed(offset))
+        .ok_or_else(|| RegistrationError::InvalidOption(format!("offset {} is too large",SystemdDuration::from(offset))))
+}
 
-        // register future beep
-        register(waketime,unit_name,command).unwrap();
+/// Same as [`register()`], but doesn't return until systemd reports the timer as loaded, for a
+/// firm guarantee that it exists. Fails with [`RegistrationError::NeverLoaded`] if it still
+/// isn't after `timeout`, in which case whatever systemd-run created is left as is.
+///
+/// A one-off timer that fires straight away with [`RegisterOptions::remain_after_elapse`] off may
+/// be gone again before it's seen, so don't combine the two.
+pub fn register_verified<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, timeout: Duration) -> Result<Registration,RegistrationError> {
+    register_verified_with_options(schedule,unit_name,command,&RegisterOptions::default(),timeout)
+}
 
-        // check future beep
-        let (_command, _datetime) = query_registration(unit_name).unwrap();
+/// Same as [`register_verified()`], with the options of [`register_with_options()`].
+pub fn register_verified_with_options<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, options: &RegisterOptions, timeout: Duration) -> Result<Registration,RegistrationError> {
+    let encoded_command = encode_command(command,options)?;
+    let systemd = Systemd::new(options.scope);
+    let unit_name = unit_name.into();
+    let (registration, _) = register_with(systemd,schedule.into(),unit_name,encoded_command,options)?;
+    wait_until_loaded(systemd,unit_name,timeout,WAIT_POLL_INTERVAL)?;
+    Ok(registration)
+}
 
-        // cancel future beep
-        let (_command, _datetime) = deregister(unit_name).unwrap();
+#[instrument(name = "wait_until_loaded", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn wait_until_loaded(systemd: Systemd, unit_name: UnitName, timeout: Duration, interval: Duration) -> Result<(),RegistrationError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if check_loaded(systemd,unit_name)? {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(RegistrationError::NeverLoaded(timeout));
+        }
+        debug!("timer not loaded yet");
+        std::thread::sleep(interval.min(deadline - now));
+    }
+}
+
+/// Same as [`register()`], but builds the [`Command`] from `program` and `args`.
+///
+/// ```no_run
+/// # let waketime = chrono::Local::now().naive_local() + chrono::Duration::minutes(1);
+/// # let unit_name = systemd_wake::UnitName::new("beep").unwrap();
+/// systemd_wake::register_simple(waketime,unit_name,"play",["-q","-n","synth","0.1","sin","880"]).unwrap();
+/// ```
+pub fn register_simple<'a, I, S>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, program: impl AsRef<OsStr>, args: I) -> Result<Registration,RegistrationError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut command = Command::new(program);
+    command.args(args);
+    register(schedule,unit_name,command)
+}
+
+/// Registers command to run once `delay` from now, using systemd's monotonic `OnActiveSec=`
+/// rather than a wall-clock time.
+pub fn register_after<'a>(delay: Duration, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<Registration,RegistrationError> {
+    register(Schedule::After(delay),unit_name,command)
+}
+
+/// Same as [`register()`], but against the service manager selected by `scope`.
+pub fn register_scoped<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, scope: Scope) -> Result<Registration,RegistrationError> {
+    let options = RegisterOptions {
+        scope,
+        ..Default::default()
+    };
+    register_with_options(schedule,unit_name,command,&options)
+}
+
+/// Deregisters its timer when dropped. Returned by [`register_guarded()`], for timers that
+/// shouldn't outlive a test or a short-lived workflow, even if it returns early or panics.
+///
+/// Failing to deregister on drop is only logged. Call [`RegistrationGuard::leak()`] to keep the
+/// timer after all.
+#[derive(Debug)]
+#[must_use = "the timer is deregistered as soon as the guard is dropped"]
+pub struct RegistrationGuard {
+    registration: Option<Registration>,
+    scope: Scope,
+}
+
+impl RegistrationGuard {
+    /// The guarded registration.
+    pub fn registration(&self) -> &Registration {
+        self.registration.as_ref().expect("registration is only taken when the guard is consumed")
+    }
+
+    /// Gives up the guard without deregistering, leaving the timer in place.
+    pub fn leak(mut self) -> Registration {
+        self.registration.take().expect("registration is only taken when the guard is consumed")
+    }
+}
+
+impl Drop for RegistrationGuard {
+    fn drop(&mut self) {
+        let Some(registration) = self.registration.take() else { return };
+        // the timer may well have cleaned up after itself already
+        match deregister_if_exists_scoped(&registration.unit_name,self.scope) {
+            Ok(_) => debug!("deregistered guarded timer {}",registration.unit_name),
+            Err(e) => warn!("failed to deregister guarded timer {}: {}",registration.unit_name,e),
+        }
+    }
+}
+
+/// Same as [`register()`], but hands back a [`RegistrationGuard`] that deregisters the timer once
+/// it goes out of scope.
+pub fn register_guarded<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<RegistrationGuard,RegistrationError> {
+    register_guarded_scoped(schedule,unit_name,command,Scope::User)
+}
+
+/// Same as [`register_guarded()`], but against the service manager selected by `scope`.
+pub fn register_guarded_scoped<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, scope: Scope) -> Result<RegistrationGuard,RegistrationError> {
+    let registration = register_scoped(schedule,unit_name,command,scope)?;
+    Ok(RegistrationGuard {
+        registration: Some(registration),
+        scope,
+    })
+}
+
+/// I/O scheduling class for the scheduled command, see `ioprio_set(2)`.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum IoClass {
+    /// Always gets the disk first. Usually requires root.
+    Realtime,
+    /// The normal class.
+    BestEffort,
+    /// Only gets the disk when nothing else wants it.
+    Idle,
+}
+
+impl IoClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IoClass::Realtime => "realtime",
+            IoClass::BestEffort => "best-effort",
+            IoClass::Idle => "idle",
+        }
+    }
+}
+
+/// What [`register_with_options()`] does about user timers when lingering is off, see
+/// [`is_lingering_enabled()`].
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq)]
+pub enum LingerCheck {
+    /// Don't check.
+    #[default]
+    Skip,
+    /// Log a warning and register anyway.
+    Warn,
+    /// Fail with [`RegistrationError::NotLingering`].
+    Require,
+}
+
+/// When systemd restarts the scheduled command after it exits, see `Restart=` in
+/// `systemd.service(5)`.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum RestartPolicy {
+    /// Never (systemd's default).
+    No,
+    /// Only after a clean exit.
+    OnSuccess,
+    /// After a non-zero exit, a signal, a timeout or a missed watchdog.
+    OnFailure,
+    /// After a signal, a timeout or a missed watchdog, but not a non-zero exit.
+    OnAbnormal,
+    /// Only after a missed watchdog.
+    OnWatchdog,
+    /// Only after an uncaught signal.
+    OnAbort,
+    /// Whatever happened.
+    Always,
+}
+
+impl RestartPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RestartPolicy::No => "no",
+            RestartPolicy::OnSuccess => "on-success",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::OnAbnormal => "on-abnormal",
+            RestartPolicy::OnWatchdog => "on-watchdog",
+            RestartPolicy::OnAbort => "on-abort",
+            RestartPolicy::Always => "always",
+        }
+    }
+}
+
+/// Formats a byte count the way systemd writes sizes, with the largest 1024-based suffix that
+/// divides it evenly, e.g. `512M`.
+fn format_bytes(bytes: u64) -> String {
+    const SUFFIXES: [(&str, u32); 4] = [("T",40),("G",30),("M",20),("K",10)];
+    for (suffix, shift) in SUFFIXES {
+        if bytes != 0 && bytes.trailing_zeros() >= shift {
+            return format!("{}{}",bytes >> shift,suffix);
+        }
+    }
+    bytes.to_string()
+}
+
+/// Optional knobs for [`register_with_options()`]. The defaults match [`register()`].
+#[derive(Clone,Debug,Default)]
+pub struct RegisterOptions {
+    /// Service manager to register with.
+    pub scope: Scope,
+    /// Sets `Persistent=true` on the timer, so a wake time missed while the machine was off or
+    /// asleep fires as soon as it's back up. Only meaningful for calendar schedules.
+    pub persistent: bool,
+    /// Sets `AccuracySec=` on the timer. systemd defaults to one minute and coalesces wake ups
+    /// within that window to save power, so tighter accuracy (e.g. one second) costs some
+    /// battery life in exchange for precise timing.
+    pub accuracy: Option<Duration>,
+    /// Sets `RandomizedDelaySec=` on the timer, delaying each elapse by a random amount up to
+    /// this long. Spreads out the load when many timers share the same schedule.
+    pub randomized_delay: Option<Duration>,
+    /// Sets `RemainAfterElapse=` on the timer. systemd defaults to keeping an elapsed one-off
+    /// timer loaded, which keeps its name taken and makes registering it again fail with
+    /// [`RegistrationError::Duplicate`] until it's deregistered. `Some(false)` lets systemd clean it
+    /// up once it has fired, at the cost of no longer being able to query it afterwards.
+    pub remain_after_elapse: Option<bool>,
+    /// Serialization format for the scheduled command. [`Format::Binary`] keeps the systemd-run
+    /// argument shorter.
+    pub format: Format,
+    /// Path to the systemd-wake helper binary the timer runs. Falls back to the `SYSTEMD_WAKE_BIN`
+    /// environment variable, then to looking up `systemd-wake` on the service's `PATH`, which
+    /// often won't include cargo's bin directory.
+    pub helper: Option<PathBuf>,
+    /// Skips the check that one-off wake times are in the future. A timer whose only wake time
+    /// has already passed elapses straight away without running anything (unless it's
+    /// [`persistent`](RegisterOptions::persistent)), so this is rarely what you want.
+    pub allow_past: bool,
+    /// Longest the encoded command may get before registering fails with
+    /// [`CommandConfigError::TooLarge`]. Defaults to [`command::MAX_ENCODED_LEN`], which is as
+    /// long as systemd-run can be handed; lower it to keep units small.
+    pub max_command_len: Option<usize>,
+    /// Skips the check that the command's program exists, either at its path or on `PATH` (the
+    /// command's own, then [`RegisterOptions::environment`]'s, then the caller's). Turn it off
+    /// when the program only shows up in the environment the timer wakes up in.
+    pub allow_missing_program: bool,
+    /// How far in the past a one-off wake time may be before it's rejected. Defaults to zero;
+    /// raise it to a few seconds to absorb the delay between computing a time and registering it.
+    pub past_tolerance: Duration,
+    /// Human-readable description shown by `systemctl list-timers` and friends. Defaults to
+    /// `systemd-wake <unit name>`.
+    pub description: Option<String>,
+    /// Unit to start if the scheduled command fails (`OnFailure=` on the service), e.g. a
+    /// notification service. Use [`query_last_exit()`] to check up on a command after the fact
+    /// instead.
+    pub on_failure: Option<String>,
+    /// Sets `Nice=` on the service, from -20 (highest priority) to 19 (lowest). Heavy background
+    /// work like backups is best run at 10 or above.
+    pub nice: Option<i8>,
+    /// Sets `IOSchedulingClass=` on the service.
+    pub io_class: Option<IoClass>,
+    /// Sets `MemoryMax=` on the service, in bytes. The command gets killed if it goes over.
+    pub memory_max: Option<u64>,
+    /// Sets `CPUQuota=` on the service, as a percentage of one CPU. Over 100 allows more than
+    /// one CPU's worth of time.
+    pub cpu_quota: Option<u32>,
+    /// Sets `TasksMax=` on the service, capping how many processes and threads it can have.
+    pub tasks_max: Option<u64>,
+    /// Variables set on the service unit itself with `--setenv=`, so systemd sees them when it
+    /// starts the helper. Unlike variables set on the [`Command`], these also affect how the
+    /// helper finds the program (e.g. `PATH`). The command inherits them, but anything set on the
+    /// [`Command`] wins, and [`CommandConfig::clear_env()`] drops them.
+    pub environment: Vec<(String,String)>,
+    /// Sets `Restart=` on the service, for commands that should be supervised until they're done
+    /// rather than fired and forgotten.
+    pub restart: Option<RestartPolicy>,
+    /// Sets `RestartSec=` on the service, how long to wait before restarting. systemd defaults
+    /// to 100ms.
+    pub restart_delay: Option<Duration>,
+    /// Sets `WatchdogSec=` on the service. The command has to ping the watchdog with
+    /// `sd_notify(3)` at least this often or systemd treats it as hung and kills it.
+    pub watchdog: Option<Duration>,
+    /// Sets `RuntimeMaxSec=` on the service, so systemd kills the command if it's still running
+    /// after this long and marks the run as [`ServiceResult::Timeout`]. Read it back with
+    /// [`query_runtime_max()`].
+    pub runtime_max: Option<Duration>,
+    /// Sets `User=` on the service, so a system timer runs its command as that user (by name or
+    /// UID) instead of root. Only valid with [`Scope::System`]; a user manager can't switch
+    /// users.
+    pub user: Option<String>,
+    /// Sets `Group=` on the service, by name or GID. Same rules as [`RegisterOptions::user`].
+    pub group: Option<String>,
+    /// Slice to run the service in, with `--slice=`, so one app's scheduled work can be limited
+    /// and accounted for together. Given without the `.slice` suffix; dashes nest, so
+    /// `myapp-jobs` lands inside `myapp.slice`.
+    pub slice: Option<String>,
+    /// Whether to check that lingering is on before registering a user timer. Without it the
+    /// user's service manager, and every timer in it, stops once the user's last session ends.
+    /// Ignored for [`Scope::System`].
+    pub linger_check: LingerCheck,
+    /// Extra `Key=value` properties for the service, passed along as `--property=`. An escape
+    /// hatch for settings without a typed option, e.g. `MemoryMax=1G` or `Nice=10`. Keys the
+    /// crate sets itself are rejected with [`RegistrationError::InvalidOption`].
+    pub extra_properties: Vec<String>,
+    /// Extra `Key=value` properties for the timer, passed along as `--timer-property=`. Same
+    /// rules as [`RegisterOptions::extra_properties`].
+    pub extra_timer_properties: Vec<String>,
+}
+
+impl RegisterOptions {
+    fn helper_program(&self) -> OsString {
+        match &self.helper {
+            Some(helper) => helper.clone().into_os_string(),
+            None => std::env::var_os("SYSTEMD_WAKE_BIN").unwrap_or_else(|| "systemd-wake".into()),
+        }
+    }
+
+    fn timer_properties(&self) -> Vec<String> {
+        let mut properties = Vec::new();
+        if self.persistent {
+            properties.push("Persistent=true".to_owned());
+        }
+        if let Some(accuracy) = self.accuracy {
+            properties.push(format!("AccuracySec={}",SystemdDuration::from(accuracy)));
+        }
+        if let Some(randomized_delay) = self.randomized_delay {
+            properties.push(format!("RandomizedDelaySec={}",SystemdDuration::from(randomized_delay)));
+        }
+        if let Some(remain_after_elapse) = self.remain_after_elapse {
+            properties.push(format!("RemainAfterElapse={}",remain_after_elapse));
+        }
+        properties
+    }
+
+    fn slice_unit(&self) -> Option<String> {
+        self.slice.as_ref().map(|slice| format!("{}.slice",slice.strip_suffix(".slice").unwrap_or(slice)))
+    }
+
+    fn service_properties(&self) -> Vec<String> {
+        let mut properties = Vec::new();
+        if let Some(on_failure) = &self.on_failure {
+            properties.push(format!("OnFailure={}",on_failure));
+        }
+        if let Some(nice) = self.nice {
+            properties.push(format!("Nice={}",nice));
+        }
+        if let Some(io_class) = self.io_class {
+            properties.push(format!("IOSchedulingClass={}",io_class.as_str()));
+        }
+        if let Some(memory_max) = self.memory_max {
+            properties.push(format!("MemoryMax={}",format_bytes(memory_max)));
+        }
+        if let Some(cpu_quota) = self.cpu_quota {
+            properties.push(format!("CPUQuota={}%",cpu_quota));
+        }
+        if let Some(tasks_max) = self.tasks_max {
+            properties.push(format!("TasksMax={}",tasks_max));
+        }
+        if let Some(restart) = self.restart {
+            properties.push(format!("Restart={}",restart.as_str()));
+        }
+        if let Some(restart_delay) = self.restart_delay {
+            properties.push(format!("RestartSec={}",SystemdDuration::from(restart_delay)));
+        }
+        if let Some(watchdog) = self.watchdog {
+            properties.push(format!("WatchdogSec={}",SystemdDuration::from(watchdog)));
+        }
+        if let Some(runtime_max) = self.runtime_max {
+            properties.push(format!("RuntimeMaxSec={}",SystemdDuration::from(runtime_max)));
+        }
+        if let Some(user) = &self.user {
+            properties.push(format!("User={}",user));
+        }
+        if let Some(group) = &self.group {
+            properties.push(format!("Group={}",group));
+        }
+        properties
+    }
+
+    /// Catches values systemd would reject, and makes sure the extra properties are `Key=value`
+    /// pairs that don't fight over a key with the properties the crate sets.
+    fn validate(&self) -> Result<(),RegistrationError> {
+        if let Some(nice) = self.nice.filter(|nice| !(-20..=19).contains(nice)) {
+            return Err(RegistrationError::InvalidOption(format!("nice level {} is outside -20..=19",nice)));
+        }
+        // a limit of zero would stop the command from ever running
+        let limits = [
+            ("memory_max",self.memory_max),
+            ("cpu_quota",self.cpu_quota.map(u64::from)),
+            ("tasks_max",self.tasks_max),
+        ];
+        if let Some((name, _)) = limits.iter().find(|(_, limit)| *limit == Some(0)) {
+            return Err(RegistrationError::InvalidOption(format!("{} must be more than zero",name)));
+        }
+        // systemd reads a zero watchdog or runtime limit as "off", which is surely not what was
+        // meant
+        let durations = [("watchdog",self.watchdog),("runtime_max",self.runtime_max)];
+        if let Some((name, _)) = durations.iter().find(|(_, duration)| *duration == Some(Duration::ZERO)) {
+            return Err(RegistrationError::InvalidOption(format!("{} must be more than zero",name)));
+        }
+        if self.restart_delay.is_some() && self.restart.is_none() {
+            return Err(RegistrationError::InvalidOption("restart_delay needs a restart policy".to_owned()));
+        }
+        for (name, value) in [("user",&self.user),("group",&self.group)] {
+            let Some(value) = value else { continue };
+            if self.scope != Scope::System {
+                return Err(RegistrationError::InvalidOption(format!("{} can only be set for system timers",name)));
+            }
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                return Err(RegistrationError::InvalidOption(format!("`{}` isn't a valid {}",value,name)));
+            }
+        }
+        if let Some(slice) = &self.slice {
+            // same rules as systemd's slice_name_is_valid()
+            let name = slice.strip_suffix(".slice").unwrap_or(slice);
+            if UnitName::new(name).is_err() || name.starts_with('-') || name.ends_with('-') || name.contains("--") {
+                return Err(RegistrationError::InvalidOption(format!("`{}` isn't a valid slice name",slice)));
+            }
+        }
+        for (key, _) in &self.environment {
+            if key.is_empty() || key.contains('=') {
+                return Err(RegistrationError::InvalidOption(format!("`{}` isn't a valid environment variable name",key)));
+            }
+            if key == COMMAND_ENV_VAR {
+                return Err(RegistrationError::InvalidOption(format!("`{}` is already set by systemd-wake",key)));
+            }
+        }
+
+        const SERVICE_KEYS: [&str; 4] = ["Description","Environment","ExecStart","SyslogIdentifier"];
+        const TIMER_KEYS: [&str; 8] = ["Description","Unit","OnCalendar","OnActiveSec","OnBootSec","OnStartupSec","OnUnitActiveSec","OnUnitInactiveSec"];
+        let key = |property: &str| property.split_once('=').map(|(key, _)| key.trim().to_owned());
+        let checks = [
+            (&self.extra_properties,&SERVICE_KEYS[..],self.service_properties().into_iter().chain(self.slice_unit().map(|slice| format!("Slice={}",slice))).collect()),
+            (&self.extra_timer_properties,&TIMER_KEYS[..],self.timer_properties()),
+        ];
+        for (extra, reserved, typed) in checks {
+            for property in extra {
+                let extra_key = key(property).filter(|key| !key.is_empty())
+                    .ok_or_else(|| RegistrationError::InvalidOption(format!("`{}` isn't a Key=value property",property)))?;
+                if reserved.contains(&extra_key.as_str()) || typed.iter().any(|typed| key(typed).as_ref() == Some(&extra_key)) {
+                    return Err(RegistrationError::InvalidOption(format!("`{}` is already set by systemd-wake",extra_key)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Same as [`register()`], but with extra systemd settings from `options`.
+pub fn register_with_options<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    register_verbose_with_options(schedule,unit_name,command,options).map(|(registration, _)| registration)
+}
+
+/// Same as [`register()`], but also hands back the raw output of systemd-run.
+pub fn register_verbose<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<(Registration,Output),RegistrationError> {
+    register_verbose_with_options(schedule,unit_name,command,&RegisterOptions::default())
+}
+
+/// Same as [`register_verbose()`], with the options of [`register_with_options()`].
+pub fn register_verbose_with_options<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, options: &RegisterOptions) -> Result<(Registration,Output),RegistrationError> {
+    let encoded_command = encode_command(command,options)?;
+    register_with(Systemd::new(options.scope),schedule.into(),unit_name.into(),encoded_command,options)
+}
+
+/// Same as [`register()`], but takes a command that's already encoded, e.g. one read back with
+/// [`query_registration_encoded()`]. Re-arming a timer this way never reconstructs the
+/// [`Command`], so nothing about it can get lost along the way. The payload is checked to decode
+/// before it's used.
+pub fn register_encoded<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, encoded_command: &str) -> Result<Registration,RegistrationError> {
+    register_encoded_scoped(schedule,unit_name,encoded_command,Scope::User)
+}
+
+/// Same as [`register_encoded()`], but against the service manager selected by `scope`.
+pub fn register_encoded_scoped<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, encoded_command: &str, scope: Scope) -> Result<Registration,RegistrationError> {
+    CommandConfig::decode_config(encoded_command)?;
+    let options = RegisterOptions {
+        scope,
+        ..Default::default()
+    };
+    register_with(Systemd::new(scope),schedule.into(),unit_name.into(),encoded_command.to_owned(),&options).map(|(registration, _)| registration)
+}
+
+/// Registers a timer that starts an existing unit, e.g. a `backup.service` installed by a package,
+/// instead of running a command through the systemd-wake helper. Only the timer is created, and
+/// since there's no command to hand back it's cancelled with [`deregister_activating()`], which
+/// leaves `target_unit` alone.
+pub fn register_activating<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, target_unit: &str) -> Result<Registration,RegistrationError> {
+    register_activating_with_options(schedule,unit_name,target_unit,&RegisterOptions::default())
+}
+
+/// Same as [`register_activating()`], but with extra timer settings from `options`. Options that
+/// only apply to the service systemd-wake would otherwise create, like
+/// [`RegisterOptions::nice`], are rejected with [`RegistrationError::InvalidOption`].
+pub fn register_activating_with_options<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, target_unit: &str, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    register_job(Systemd::new(options.scope),schedule.into(),unit_name.into(),Job::Unit(target_unit),options).map(|(registration, _)| registration)
+}
+
+/// Builds the systemd-run invocation [`register_with_options()`] would run and returns its argv
+/// without running it, for debugging naming and escaping problems.
+///
+/// Nothing is sent to systemd, so unlike a real registration this won't catch duplicate units.
+pub fn register_dry_run<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, options: &RegisterOptions) -> Result<Vec<String>,RegistrationError> {
+    let schedule = schedule.into();
+    check_waketime(&schedule,options)?;
+    let encoded_command = encode_command(command,options)?;
+    let systemd_command = register_command(Systemd::new(options.scope),&schedule,unit_name.into(),&Job::Command(&encoded_command),options)?;
+    Ok(argv(&systemd_command))
+}
+
+fn argv(command: &Command) -> Vec<String> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// What a timer starts when it fires.
+enum Job<'j> {
+    /// The systemd-wake helper, running this encoded command.
+    Command(&'j str),
+    /// An existing unit, set as the timer's `Unit=`.
+    Unit(&'j str),
+}
+
+/// Checks the command can be found, unless told otherwise, and encodes it.
+fn encode_command(command: impl Into<CommandConfig>, options: &RegisterOptions) -> Result<String,RegistrationError> {
+    let command = command.into();
+    if !options.allow_missing_program {
+        let path = match options.environment.iter().find(|(key, _)| key == "PATH") {
+            Some((_, path)) => Some(OsString::from(path)),
+            None => std::env::var_os("PATH"),
+        };
+        if let Some(program) = command.missing_program(path.as_deref()) {
+            return Err(RegistrationError::ProgramNotFound(program.to_owned()));
+        }
+    }
+    let max_len = options.max_command_len.unwrap_or(command::MAX_ENCODED_LEN);
+    Ok(CommandConfig::encode_with_limit(command,options.format,max_len)?)
+}
+
+/// Registers against `systemd`, which takes precedence over `options.scope`.
+fn register_with(systemd: Systemd, schedule: Schedule, unit_name: UnitName, encoded_command: String, options: &RegisterOptions) -> Result<(Registration,Output),RegistrationError> {
+    register_job(systemd,schedule,unit_name,Job::Command(&encoded_command),options)
+}
+
+#[instrument(name = "register", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn register_job(systemd: Systemd, schedule: Schedule, unit_name: UnitName, job: Job, options: &RegisterOptions) -> Result<(Registration,Output),RegistrationError> {
+    debug!("registering timer");
+
+    check_waketime(&schedule,options)?;
+
+    if needs_linger_check(systemd,options) {
+        check_lingering(options,is_lingering_enabled_with(systemd)?)?;
+    }
+
+    check_name_free(systemd,unit_name)?;
+
+    let systemd_command = register_command(systemd,&schedule,unit_name,&job,options)?;
+
+    debug!("running timer command: {:?}",systemd_command);
+    let output = systemd.run(systemd_command).map_err(run_error)?;
+    let mut registration = Registration::from_output(unit_name,schedule,&output);
+    if let Job::Unit(target_unit) = job {
+        registration.service_unit = target_unit.to_owned();
+    }
+    debug!("registered {} -> {}",registration.timer_unit,registration.service_unit);
+
+    // the timer exists by now, so don't fail the registration over this
+    match extract_unit_properties(systemd,&registration.timer_unit,&["NextElapseUSecRealtime"]) {
+        Ok(properties) => registration.next_elapse = parse_timestamp(&properties["NextElapseUSecRealtime"]),
+        Err(e) => debug!("couldn't read next elapse: {}",e),
+    }
+    Ok((registration,output))
+}
+
+fn needs_linger_check(systemd: Systemd, options: &RegisterOptions) -> bool {
+    systemd.scope == Scope::User && options.linger_check != LingerCheck::Skip
+}
+
+fn check_lingering(options: &RegisterOptions, lingering: bool) -> Result<(),RegistrationError> {
+    if !lingering {
+        if options.linger_check == LingerCheck::Require {
+            return Err(RegistrationError::NotLingering);
+        }
+        warn!("lingering is off, so the timer won't fire while the user is logged out (see `loginctl enable-linger`)");
+    }
+    Ok(())
+}
+
+/// systemd refuses to create a transient unit that already exists, so the check before running
+/// systemd-run can't be raced past. Turns that refusal into the same errors the check gives.
+fn run_error(error: CommandError) -> RegistrationError {
+    if let CommandError::CommandFailed { stderr, .. } = &error {
+        if let Some(existing) = existing_unit_error(stderr) {
+            return existing;
+        }
+    }
+    error.into()
+}
+
+/// Picks out systemd refusing to create a unit that already exists, e.g. "Failed to start
+/// transient timer unit: Unit foo.timer was already loaded or has a fragment file." on current
+/// systemd, or "... Unit foo.timer already exists." on older ones.
+fn existing_unit_error(message: &str) -> Option<RegistrationError> {
+    let unit = message.split("Unit ").skip(1).find_map(|rest| {
+        rest.split_once(" was already loaded")
+            .or_else(|| rest.split_once(" already exists"))
+            .map(|(unit, _)| unit)
+    })?;
+    if unit.ends_with(".timer") {
+        Some(RegistrationError::Duplicate)
+    } else {
+        Some(RegistrationError::NameConflict(unit.to_owned()))
+    }
+}
+
+fn check_waketime(schedule: &Schedule, options: &RegisterOptions) -> Result<(),RegistrationError> {
+    if !options.allow_past {
+        if let Some(waketime) = schedule.waketime() {
+            // a tolerance reaching back past what chrono can represent means no lower bound at all
+            let earliest = chrono::Duration::from_std(options.past_tolerance).ok()
+                .and_then(|tolerance| Local::now().naive_local().checked_sub_signed(tolerance));
+            if earliest.is_some_and(|earliest| waketime < earliest) {
+                return Err(RegistrationError::TimeInPast(waketime));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn register_command(systemd: Systemd, schedule: &Schedule, unit_name: UnitName, job: &Job, options: &RegisterOptions) -> Result<Command,RegistrationError> {
+    options.validate()?;
+    if let Job::Unit(target_unit) = job {
+        UnitName::new(target_unit).map_err(|e| RegistrationError::InvalidOption(format!("target unit `{}`: {}",target_unit,e)))?;
+        // no service gets created, so there's nothing for these to apply to
+        if !options.service_properties().is_empty() || !options.extra_properties.is_empty() || !options.environment.is_empty() || options.slice.is_some() {
+            return Err(RegistrationError::InvalidOption("service options can't be used when activating an existing unit".to_owned()));
+        }
+    }
+
+    let timer_args = schedule.timer_args();
+    debug!("timer set for {:?}",timer_args);
+
+    let description = match &options.description {
+        Some(description) => description.clone(),
+        None => format!("systemd-wake {}",unit_name),
+    };
+
+    let mut systemd_command = systemd.systemd_run();
+    systemd_command
+        .arg(format!("--unit={}",unit_name))
+        .arg(format!("--description={}",description));
+    if let Job::Command(encoded_command) = job {
+        systemd_command
+            .arg(format!("--setenv={}={}",COMMAND_ENV_VAR,encoded_command))
+            // so `journalctl -t <unit name>` finds what the command printed
+            .arg(format!("--property=SyslogIdentifier={}",unit_name));
+        for (key, value) in &options.environment {
+            systemd_command.arg(format!("--setenv={}={}",key,value));
+        }
+    }
+    systemd_command.args(timer_args);
+    for property in options.timer_properties().iter().chain(&options.extra_timer_properties) {
+        systemd_command.arg(format!("--timer-property={}",property));
+    }
+    match job {
+        Job::Command(_) => {
+            if let Some(slice) = options.slice_unit() {
+                systemd_command.arg(format!("--slice={}",slice));
+            }
+            for property in options.service_properties().iter().chain(&options.extra_properties) {
+                systemd_command.arg(format!("--property={}",property));
+            }
+            systemd_command.arg(options.helper_program());
+        },
+        // without a command systemd-run only creates the timer
+        Job::Unit(target_unit) => {
+            systemd_command.arg(format!("--timer-property=Unit={}",target_unit));
+        },
+    }
+    Ok(systemd_command)
+}
+
+/// Builder for registrations that need more than [`register()`] offers.
+///
+/// ```no_run
+/// # use systemd_wake::*;
+/// # let waketime = chrono::Local::now().naive_local() + chrono::Duration::hours(8);
+/// let unit_name = UnitName::new("nightly-backup").unwrap();
+/// let command = std::process::Command::new("backup");
+/// RegisterBuilder::new(unit_name,command)
+///     .at(waketime)
+///     .persistent(true)
+///     .accuracy(std::time::Duration::from_secs(1))
+///     .scope(Scope::User)
+///     .run()
+///     .unwrap();
+/// ```
+pub struct RegisterBuilder<'a> {
+    unit_name: UnitName<'a>,
+    command: CommandConfig,
+    schedule: Option<Schedule>,
+    options: RegisterOptions,
+}
+
+impl<'a> RegisterBuilder<'a> {
+    /// Starts a registration of `command` under `unit_name`. A schedule must be set with
+    /// [`RegisterBuilder::at()`] before calling [`RegisterBuilder::run()`].
+    pub fn new(unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Self {
+        Self {
+            unit_name: unit_name.into(),
+            command: command.into(),
+            schedule: None,
+            options: RegisterOptions::default(),
+        }
+    }
+
+    /// Sets when the timer fires.
+    pub fn at(mut self, schedule: impl Into<Schedule>) -> Self {
+        self.schedule = Some(schedule.into());
+        self
+    }
+
+    /// Replaces all options at once.
+    pub fn options(mut self, options: RegisterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// See [`RegisterOptions::scope`].
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.options.scope = scope;
+        self
+    }
+
+    /// See [`RegisterOptions::persistent`].
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        self.options.persistent = persistent;
+        self
+    }
+
+    /// See [`RegisterOptions::accuracy`].
+    pub fn accuracy(mut self, accuracy: Duration) -> Self {
+        self.options.accuracy = Some(accuracy);
+        self
+    }
+
+    /// See [`RegisterOptions::randomized_delay`].
+    pub fn randomized_delay(mut self, randomized_delay: Duration) -> Self {
+        self.options.randomized_delay = Some(randomized_delay);
+        self
+    }
+
+    /// See [`RegisterOptions::remain_after_elapse`].
+    pub fn remain_after_elapse(mut self, remain_after_elapse: bool) -> Self {
+        self.options.remain_after_elapse = Some(remain_after_elapse);
+        self
+    }
+
+    /// See [`RegisterOptions::allow_past`].
+    pub fn allow_past(mut self, allow_past: bool) -> Self {
+        self.options.allow_past = allow_past;
+        self
+    }
+
+    /// See [`RegisterOptions::allow_missing_program`].
+    pub fn allow_missing_program(mut self, allow_missing_program: bool) -> Self {
+        self.options.allow_missing_program = allow_missing_program;
+        self
+    }
+
+    /// See [`RegisterOptions::past_tolerance`].
+    pub fn past_tolerance(mut self, past_tolerance: Duration) -> Self {
+        self.options.past_tolerance = past_tolerance;
+        self
+    }
+
+    /// See [`RegisterOptions::description`].
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.options.description = Some(description.into());
+        self
+    }
+
+    /// See [`RegisterOptions::on_failure`].
+    pub fn on_failure(mut self, on_failure: impl Into<String>) -> Self {
+        self.options.on_failure = Some(on_failure.into());
+        self
+    }
+
+    /// Adds one of [`RegisterOptions::environment`].
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.environment.push((key.into(),value.into()));
+        self
+    }
+
+    /// Adds one of [`RegisterOptions::extra_properties`].
+    pub fn property(mut self, property: impl Into<String>) -> Self {
+        self.options.extra_properties.push(property.into());
+        self
+    }
+
+    /// Adds one of [`RegisterOptions::extra_timer_properties`].
+    pub fn timer_property(mut self, property: impl Into<String>) -> Self {
+        self.options.extra_timer_properties.push(property.into());
+        self
+    }
+
+    /// See [`RegisterOptions::nice`].
+    pub fn nice(mut self, nice: i8) -> Self {
+        self.options.nice = Some(nice);
+        self
+    }
+
+    /// See [`RegisterOptions::io_class`].
+    pub fn io_class(mut self, io_class: IoClass) -> Self {
+        self.options.io_class = Some(io_class);
+        self
+    }
+
+    /// See [`RegisterOptions::memory_max`].
+    pub fn memory_max(mut self, bytes: u64) -> Self {
+        self.options.memory_max = Some(bytes);
+        self
+    }
+
+    /// See [`RegisterOptions::cpu_quota`].
+    pub fn cpu_quota(mut self, percent: u32) -> Self {
+        self.options.cpu_quota = Some(percent);
+        self
+    }
+
+    /// See [`RegisterOptions::tasks_max`].
+    pub fn tasks_max(mut self, tasks: u64) -> Self {
+        self.options.tasks_max = Some(tasks);
+        self
+    }
+
+    /// See [`RegisterOptions::restart`].
+    pub fn restart(mut self, restart: RestartPolicy) -> Self {
+        self.options.restart = Some(restart);
+        self
+    }
+
+    /// See [`RegisterOptions::restart_delay`].
+    pub fn restart_delay(mut self, restart_delay: Duration) -> Self {
+        self.options.restart_delay = Some(restart_delay);
+        self
+    }
+
+    /// See [`RegisterOptions::watchdog`].
+    pub fn watchdog(mut self, watchdog: Duration) -> Self {
+        self.options.watchdog = Some(watchdog);
+        self
+    }
+
+    /// See [`RegisterOptions::runtime_max`].
+    pub fn runtime_max(mut self, runtime_max: Duration) -> Self {
+        self.options.runtime_max = Some(runtime_max);
+        self
+    }
+
+    /// See [`RegisterOptions::user`].
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.options.user = Some(user.into());
+        self
+    }
+
+    /// See [`RegisterOptions::group`].
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.options.group = Some(group.into());
+        self
+    }
+
+    /// See [`RegisterOptions::slice`].
+    pub fn slice(mut self, slice: impl Into<String>) -> Self {
+        self.options.slice = Some(slice.into());
+        self
+    }
+
+    /// See [`RegisterOptions::linger_check`].
+    pub fn linger_check(mut self, linger_check: LingerCheck) -> Self {
+        self.options.linger_check = linger_check;
+        self
+    }
+
+    /// See [`RegisterOptions::format`].
+    pub fn format(mut self, format: Format) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    /// See [`RegisterOptions::max_command_len`].
+    pub fn max_command_len(mut self, max_command_len: usize) -> Self {
+        self.options.max_command_len = Some(max_command_len);
+        self
+    }
+
+    /// See [`RegisterOptions::helper`].
+    pub fn helper(mut self, helper: impl Into<PathBuf>) -> Self {
+        self.options.helper = Some(helper.into());
+        self
+    }
+
+    /// Registers the timer.
+    pub fn run(self) -> Result<Registration,RegistrationError> {
+        self.run_verbose().map(|(registration, _)| registration)
+    }
+
+    /// Same as [`RegisterBuilder::run()`], but also hands back the raw systemd-run output.
+    pub fn run_verbose(self) -> Result<(Registration,Output),RegistrationError> {
+        let schedule = self.schedule.ok_or(RegistrationError::NoSchedule)?;
+        let encoded_command = encode_command(self.command,&self.options)?;
+        register_with(Systemd::new(self.options.scope),schedule,self.unit_name,encoded_command,&self.options)
+    }
+
+    /// Returns the systemd-run argv [`RegisterBuilder::run()`] would execute. See
+    /// [`register_dry_run()`].
+    pub fn dry_run(self) -> Result<Vec<String>,RegistrationError> {
+        let schedule = self.schedule.ok_or(RegistrationError::NoSchedule)?;
+        register_dry_run(schedule,self.unit_name,self.command,&self.options)
+    }
+}
+
+/// Calls systemctl to deregister specified timer, then resets any failed state on the generated
+/// units. A timer that's already stopped, or that systemd unloaded after it was looked up, counts
+/// as deregistered; only genuine failures to stop it are errors.
+pub fn deregister<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(Command,TimerSpec),RegistrationError> {
+    deregister_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`deregister()`], but against the service manager selected by `scope`.
+pub fn deregister_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(Command,TimerSpec),RegistrationError> {
+    deregister_verbose_scoped(unit_name,scope).map(|(command, deadline, _)| (command,deadline))
+}
+
+/// Same as [`deregister()`], but also hands back the raw output of `systemctl stop`.
+pub fn deregister_verbose<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(Command,TimerSpec,Output),RegistrationError> {
+    deregister_verbose_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`deregister_verbose()`], but against the service manager selected by `scope`.
+pub fn deregister_verbose_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(Command,TimerSpec,Output),RegistrationError> {
+    deregister_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "deregister", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn deregister_with(systemd: Systemd, unit_name: UnitName) -> Result<(Command,TimerSpec,Output),RegistrationError> {
+    let (command, deadline) = query_registration_with(systemd,unit_name)?;
+    let output = stop_timer(systemd,unit_name)?;
+    Ok((command.into(),deadline,output))
+}
+
+fn stop_timer(systemd: Systemd, unit_name: UnitName) -> Result<Output,CommandError> {
+    debug!("deregistering timer");
+
+    let systemd_command = stop_command(systemd,unit_name);
+    debug!("running stop timer command: {:?}",systemd_command);
+    let output = systemd.run(systemd_command).or_else(already_stopped)?;
+
+    reset_failed(systemd,unit_name);
+    Ok(output)
+}
+
+fn stop_command(systemd: Systemd, unit_name: UnitName) -> Command {
+    let mut systemd_command = systemd.systemctl();
+    systemd_command
+        .arg("stop")
+        .arg(unit_name.timer_unit());
+    systemd_command
+}
+
+/// Treats `systemctl stop` failing because the timer is gone as success, handing back its output,
+/// so cancelling works whatever state the timer is in. Stopping a loaded timer succeeds even when
+/// it's inactive or failed, but one that elapsed with `RemainAfterElapse=no` can be unloaded
+/// between being looked up and being stopped.
+fn already_stopped(error: CommandError) -> Result<Output,CommandError> {
+    match error {
+        // e.g. "Failed to stop foo.timer: Unit foo.timer not loaded."
+        CommandError::CommandFailed { status, stdout, stderr } if stderr.contains(" not loaded") => {
+            debug!("timer already gone: {}",stderr.trim_end());
+            Ok(Output {
+                status,
+                stdout: stdout.into_bytes(),
+                stderr: stderr.into_bytes(),
+            })
+        },
+        error => Err(error),
+    }
+}
+
+/// Cancels a timer from [`register_activating()`], returning its settings.
+pub fn deregister_activating<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<TimerSpec,RegistrationError> {
+    deregister_activating_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`deregister_activating()`], but against the service manager selected by `scope`.
+pub fn deregister_activating_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<TimerSpec,RegistrationError> {
+    deregister_activating_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "deregister_activating", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn deregister_activating_with(systemd: Systemd, unit_name: UnitName) -> Result<TimerSpec,RegistrationError> {
+    let properties = extract_properties(systemd,unit_name,&["LoadState","TimersCalendar","TimersMonotonic"])?;
+    if properties["LoadState"] != "loaded" {
+        return Err(QueryError::NotLoaded.into());
+    }
+    stop_timer(systemd,unit_name)?;
+    Ok(TimerSpec::parse(&properties["TimersCalendar"],&properties["TimersMonotonic"]))
+}
+
+/// Per-timer outcome of [`deregister_prefix()`].
+pub type PrefixDeregistration = Vec<(UnitNameBuf,Result<(),CommandError>)>;
+
+/// Deregisters every timer from [`list_registrations()`] whose name starts with `prefix`, e.g.
+/// to clean up after an app that namespaces its units. Keeps going when a timer fails to stop
+/// and reports how each one went.
+pub fn deregister_prefix(prefix: &str) -> Result<PrefixDeregistration,QueryError> {
+    deregister_prefix_scoped(prefix,Scope::User)
+}
+
+/// Same as [`deregister_prefix()`], but against the service manager selected by `scope`.
+pub fn deregister_prefix_scoped(prefix: &str, scope: Scope) -> Result<PrefixDeregistration,QueryError> {
+    deregister_prefix_with(Systemd::new(scope),prefix)
+}
+
+#[instrument(name = "deregister_prefix", level = "debug", skip(systemd))]
+fn deregister_prefix_with(systemd: Systemd, prefix: &str) -> Result<PrefixDeregistration,QueryError> {
+    Ok(list_registrations_with(systemd)?.into_iter()
+        .filter(|timer| timer.unit_name.as_ref().starts_with(prefix))
+        .map(|timer| {
+            let result = stop_timer(systemd,timer.unit_name.as_unit_name()).map(|_| ());
+            (timer.unit_name,result)
+        })
+        .collect())
+}
+
+/// Clears any failed state left on the transient units, so systemd can garbage collect them and
+/// repeated schedule/cancel cycles don't leave cruft behind. Failures are only logged, since most
+/// of the time there's simply nothing to reset.
+fn reset_failed(systemd: Systemd, unit_name: UnitName) {
+    let systemd_command = reset_failed_command(systemd,unit_name);
+    debug!("running reset-failed command: {:?}",systemd_command);
+    if let Err(e) = systemd.run(systemd_command) {
+        debug!("nothing reset: {}",e);
+    }
+}
+
+fn reset_failed_command(systemd: Systemd, unit_name: UnitName) -> Command {
+    let mut systemd_command = systemd.systemctl();
+    systemd_command
+        .arg("reset-failed")
+        .arg(unit_name.service_unit())
+        .arg(unit_name.timer_unit());
+    systemd_command
+}
+
+/// Like [`deregister()`], but returns `Ok(None)` instead of an error when no timer with that name
+/// is loaded. Handy for idempotent cleanup.
+pub fn deregister_if_exists<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<Option<(Command,TimerSpec)>,RegistrationError> {
+    deregister_if_exists_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`deregister_if_exists()`], but against the service manager selected by `scope`.
+pub fn deregister_if_exists_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<Option<(Command,TimerSpec)>,RegistrationError> {
+    deregister_if_exists_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "deregister_if_exists", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn deregister_if_exists_with(systemd: Systemd, unit_name: UnitName) -> Result<Option<(Command,TimerSpec)>,RegistrationError> {
+    if !check_loaded(systemd,unit_name)? {
+        debug!("timer not loaded, nothing to deregister");
+        return Ok(None);
+    }
+    let (command, deadline, _) = deregister_with(systemd,unit_name)?;
+    Ok(Some((command,deadline)))
+}
+
+/// Convenience function for changing scheduled waketime. See [`update()`] for what happens if
+/// the new schedule can't be registered.
+pub fn reschedule<'a>(unit_name: impl Into<UnitName<'a>>, waketime: impl Into<Schedule>) -> Result<Registration,RegistrationError> {
+    reschedule_scoped(unit_name,waketime,Scope::User)
+}
+
+/// Same as [`reschedule()`], but against the service manager selected by `scope`.
+pub fn reschedule_scoped<'a>(unit_name: impl Into<UnitName<'a>>, waketime: impl Into<Schedule>, scope: Scope) -> Result<Registration,RegistrationError> {
+    let options = RegisterOptions {
+        scope,
+        ..Default::default()
+    };
+    reschedule_with_options(unit_name,waketime,&options)
+}
+
+/// Same as [`reschedule()`], but registers the new timer with `options`, see
+/// [`update_with_options()`].
+pub fn reschedule_with_options<'a>(unit_name: impl Into<UnitName<'a>>, waketime: impl Into<Schedule>, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    replace(Systemd::new(options.scope),unit_name.into(),waketime.into(),None,options)
+}
+
+/// Replaces an existing timer's schedule and command.
+///
+/// systemd can't swap a transient unit in place, so the old timer is stopped before the new one
+/// is registered and there's a short window where neither exists. If registering the replacement
+/// fails, the old timer is put back where possible and [`RegistrationError::UpdateFailed`] says
+/// whether that worked. systemd can't hand back the [`RegisterOptions`] a timer was registered
+/// with, so use [`update_with_options()`] to keep them.
+pub fn update<'a>(unit_name: impl Into<UnitName<'a>>, schedule: impl Into<Schedule>, command: impl Into<CommandConfig>) -> Result<Registration,RegistrationError> {
+    update_scoped(unit_name,schedule,command,Scope::User)
+}
+
+/// Same as [`update()`], but against the service manager selected by `scope`.
+pub fn update_scoped<'a>(unit_name: impl Into<UnitName<'a>>, schedule: impl Into<Schedule>, command: impl Into<CommandConfig>, scope: Scope) -> Result<Registration,RegistrationError> {
+    let options = RegisterOptions {
+        scope,
+        ..Default::default()
+    };
+    update_with_options(unit_name,schedule,command,&options)
+}
+
+/// Same as [`update()`], but registers the replacement with `options`, the same way
+/// [`register_with_options()`] does. Passing the options the timer was first registered with
+/// keeps its settings across the update, and they're used to restore the old timer too.
+pub fn update_with_options<'a>(unit_name: impl Into<UnitName<'a>>, schedule: impl Into<Schedule>, command: impl Into<CommandConfig>, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    replace(Systemd::new(options.scope),unit_name.into(),schedule.into(),Some(command.into()),options)
+}
+
+/// Swaps out a timer, keeping the old command if `command` is `None`.
+#[instrument(name = "replace", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn replace(systemd: Systemd, unit_name: UnitName, schedule: Schedule, command: Option<CommandConfig>, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    let (old_command, old_spec) = query_encoded_with(systemd,unit_name)?;
+    // encode before stopping anything, and reuse the stored payload as is when keeping the command
+    let command = match command {
+        Some(command) => encode_command(command,options)?,
+        None => old_command.clone(),
+    };
+    stop_timer(systemd,unit_name)?;
+
+    let error = match register_with(systemd,schedule,unit_name,command,options) {
+        Ok((registration, _)) => return Ok(registration),
+        Err(error) => error,
+    };
+
+    warn!("registering replacement failed, restoring old timer");
+    let options = RegisterOptions {
+        allow_past: true,
+        ..options.clone()
+    };
+    let restored = match old_spec.schedule() {
+        Some(old_schedule) => register_with(systemd,old_schedule,unit_name,old_command,&options).is_ok(),
+        None => false,
+    };
+    Err(RegistrationError::UpdateFailed {
+        source: Box::new(error),
+        restored,
+    })
+}
+
+/// A timer taken down by [`pause()`], with what [`resume()`] needs to put it back. Nothing about
+/// it is left with systemd, so the caller has to hold onto it (with the `serde` feature it can be
+/// written out) or the timer is gone for good.
+#[derive(Clone,Debug,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct SavedRegistration {
+    /// Unit name the timer was registered under.
+    pub unit_name: UnitNameBuf,
+    /// Schedule to register it with again.
+    pub schedule: Schedule,
+    /// The command, encoded as it was stored on the service.
+    pub encoded_command: String,
+}
+
+/// Stops a timer from firing while keeping its definition, for a "pause" or "snooze all" mode.
+/// systemd can't pause a transient unit, so the timer is deregistered and its schedule and
+/// command are handed back for [`resume()`].
+///
+/// Only the schedule and command are kept, not options like [`RegisterOptions::persistent`].
+/// Timers counting from when they were started (like [`Schedule::After`]) can't be put back
+/// faithfully, so they're left running and [`RegistrationError::NotRecreatable`] is returned.
+pub fn pause<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<SavedRegistration,RegistrationError> {
+    pause_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`pause()`], but against the service manager selected by `scope`.
+pub fn pause_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<SavedRegistration,RegistrationError> {
+    pause_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "pause", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn pause_with(systemd: Systemd, unit_name: UnitName) -> Result<SavedRegistration,RegistrationError> {
+    let (encoded_command, spec) = query_encoded_with(systemd,unit_name)?;
+    let schedule = spec.schedule().ok_or(RegistrationError::NotRecreatable)?;
+    stop_timer(systemd,unit_name)?;
+    Ok(SavedRegistration {
+        unit_name: unit_name.into(),
+        schedule,
+        encoded_command,
+    })
+}
+
+/// Registers a timer taken down by [`pause()`] again. A one-off wake time that passed while it
+/// was paused fails with [`RegistrationError::TimeInPast`]; register it with
+/// [`register_encoded()`] and a new schedule instead.
+pub fn resume(saved: &SavedRegistration) -> Result<Registration,RegistrationError> {
+    resume_scoped(saved,Scope::User)
+}
+
+/// Same as [`resume()`], but against the service manager selected by `scope`.
+pub fn resume_scoped(saved: &SavedRegistration, scope: Scope) -> Result<Registration,RegistrationError> {
+    let options = RegisterOptions {
+        scope,
+        ..Default::default()
+    };
+    resume_with(Systemd::new(scope),saved,&options)
+}
+
+fn resume_with(systemd: Systemd, saved: &SavedRegistration, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    register_with(systemd,saved.schedule.clone(),saved.unit_name.as_unit_name(),saved.encoded_command.clone(),options).map(|(registration, _)| registration)
+}
+
+/// Reads several properties of the unit's timer in a single `systemctl show` call. Properties
+/// systemd leaves out of its output come back empty.
+fn extract_properties(systemd: Systemd, unit_name: UnitName, properties: &[&str]) -> Result<BTreeMap<String,String>,QueryError> {
+    extract_unit_properties(systemd,&unit_name.timer_unit(),properties)
+}
+
+fn extract_unit_properties(systemd: Systemd, unit: &str, properties: &[&str]) -> Result<BTreeMap<String,String>,QueryError> {
+    let mut values = show_units(systemd,&[unit],properties)?.pop().unwrap_or_default();
+    for property in properties {
+        values.entry(property.to_string()).or_default();
+    }
+    Ok(values)
+}
+
+/// Whether lingering is on for the calling user, i.e. whether their service manager keeps running
+/// while they're logged out. Without it user timers only fire while the user has a session, which
+/// is the usual reason one "never fired". Turn it on with `loginctl enable-linger`.
+pub fn is_lingering_enabled() -> Result<bool,QueryError> {
+    is_lingering_enabled_with(Systemd::new(Scope::User))
+}
+
+fn is_lingering_enabled_with(systemd: Systemd) -> Result<bool,QueryError> {
+    let loginctl = linger_command(systemd)?;
+    lingering(systemd.run(loginctl))
+}
+
+fn linger_command(systemd: Systemd) -> Result<Command,CommandError> {
+    let mut loginctl = systemd.loginctl();
+    loginctl
+        .arg("show-user")
+        .arg(current_uid()?.to_string())
+        .arg("--property=Linger");
+    Ok(loginctl)
+}
+
+fn lingering(result: Result<Output,CommandError>) -> Result<bool,QueryError> {
+    match result {
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).trim() == "Linger=yes"),
+        // logind only knows about users that are logged in or lingering
+        Err(CommandError::CommandFailed { stderr, .. }) if stderr.contains("is not logged in or lingering") => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(unix)]
+fn current_uid() -> Result<u32,CommandError> {
+    use std::os::unix::fs::MetadataExt;
+
+    // /proc/self belongs to whoever the process runs as
+    Ok(std::fs::metadata("/proc/self")?.uid())
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> Result<u32,CommandError> {
+    Err(CommandError::UnsupportedPlatform(std::env::consts::OS))
+}
+
+fn check_loaded(systemd: Systemd, unit_name: UnitName) -> Result<bool,QueryError> {
+    Ok(extract_properties(systemd,unit_name,&["LoadState"])?["LoadState"] == "loaded")
+}
+
+/// Makes sure neither unit systemd-run is about to create exists yet. A loaded timer means the
+/// name is already registered, while a lone service means something else took it.
+fn check_name_free(systemd: Systemd, unit_name: UnitName) -> Result<(),RegistrationError> {
+    let units = show_units(systemd,&[&unit_name.timer_unit(),&unit_name.service_unit()],&["LoadState"])?;
+    name_free(unit_name,units)
+}
+
+/// Checks the `LoadState` of the timer and service, in that order.
+fn name_free(unit_name: UnitName, units: Vec<BTreeMap<String,String>>) -> Result<(),RegistrationError> {
+    let service_name = unit_name.service_unit();
+    let mut units = units.into_iter();
+    let timer = units.next().unwrap_or_default();
+    let service = units.next().unwrap_or_default();
+    if property(&timer,"LoadState") == "loaded" {
+        return Err(RegistrationError::Duplicate);
+    }
+    if property(&service,"LoadState") == "loaded" {
+        return Err(RegistrationError::NameConflict(service_name));
+    }
+    Ok(())
+}
+
+/// Returns whether a timer with this name is currently loaded, i.e. whether [`register()`] would
+/// fail with [`RegistrationError::Duplicate`]. Cheaper than [`query_registration()`] since
+/// nothing is decoded.
+pub fn is_registered<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<bool,QueryError> {
+    is_registered_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`is_registered()`], but against the service manager selected by `scope`.
+pub fn is_registered_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<bool,QueryError> {
+    check_loaded(Systemd::new(scope),unit_name.into())
+}
+
+/// Returns registered command and wake up time for unit if it exists.
+///
+/// One-off calendar entries come back as [`Schedule::At`]. Anything systemd reports that isn't a
+/// single timestamp (e.g. a recurring expression) comes back verbatim as [`Schedule::Calendar`].
+/// Use [`TimerSpec::waketime()`] for the common single wake time case.
+///
+/// Timers that weren't registered through this crate fail with [`QueryError::NotOwned`], while
+/// [`QueryError::DecodeError`] means the stored command is there but corrupt.
+pub fn query_registration<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(Command,TimerSpec),QueryError> {
+    query_registration_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_registration()`], but against the service manager selected by `scope`.
+pub fn query_registration_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(Command,TimerSpec),QueryError> {
+    let (config, timer_spec) = query_registration_config_scoped(unit_name,scope)?;
+    Ok((config.into(),timer_spec))
+}
+
+/// Same as [`query_registration()`], but returns the decoded [`CommandConfig`] so the scheduled
+/// command can be inspected or re-serialized without running it.
+pub fn query_registration_config<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(CommandConfig,TimerSpec),QueryError> {
+    query_registration_config_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_registration_config()`], but against the service manager selected by `scope`.
+pub fn query_registration_config_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(CommandConfig,TimerSpec),QueryError> {
+    query_registration_with(Systemd::new(scope),unit_name.into())
+}
+
+fn query_registration_with(systemd: Systemd, unit_name: UnitName) -> Result<(CommandConfig,TimerSpec),QueryError> {
+    let (encoded_command, timer_spec) = query_encoded_with(systemd,unit_name)?;
+    Ok((CommandConfig::decode_config(encoded_command)?,timer_spec))
+}
+
+/// Same as [`query_registration()`], but returns the command still encoded, ready to hand to
+/// [`register_encoded()`].
+pub fn query_registration_encoded<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(String,TimerSpec),QueryError> {
+    query_registration_encoded_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_registration_encoded()`], but against the service manager selected by `scope`.
+pub fn query_registration_encoded_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(String,TimerSpec),QueryError> {
+    query_encoded_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "query_registration", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn query_encoded_with(systemd: Systemd, unit_name: UnitName) -> Result<(String,TimerSpec),QueryError> {
+    debug!("querying registration");
+
+    let units = show_units(systemd,&[&unit_name.timer_unit(),&unit_name.service_unit()],QUERY_PROPERTIES)?;
+    registration_from_units(units)
+}
+
+/// What [`registration_from_units()`] needs to see of the timer and the service.
+const QUERY_PROPERTIES: &[&str] = &["LoadState","Description","TimersCalendar","TimersMonotonic","Environment"];
+
+/// Reads the encoded command and timer settings out of the timer's and service's properties, in
+/// that order.
+fn registration_from_units(units: Vec<BTreeMap<String,String>>) -> Result<(String,TimerSpec),QueryError> {
+    let mut units = units.into_iter();
+    let timer = units.next().unwrap_or_default();
+    let service = units.next().unwrap_or_default();
+    if property(&timer,"LoadState") != "loaded" {
+        return Err(QueryError::NotLoaded);
+    }
+
+    let encoded_command = registration_payload(property(&service,"Environment"),property(&timer,"Description"))?;
+
+    Ok((encoded_command,TimerSpec::parse(property(&timer,"TimersCalendar"),property(&timer,"TimersMonotonic"))))
+}
+
+/// Returns the unit the timer starts when it fires (its `Unit=`), e.g. `my-unit.service` for a
+/// timer from [`register()`] or the target of one from [`register_activating()`].
+pub fn query_target<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<String,QueryError> {
+    query_target_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_target()`], but against the service manager selected by `scope`.
+pub fn query_target_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<String,QueryError> {
+    query_target_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "query_target", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn query_target_with(systemd: Systemd, unit_name: UnitName) -> Result<String,QueryError> {
+    let properties = extract_properties(systemd,unit_name,&["LoadState","Unit"])?;
+    if properties["LoadState"] != "loaded" {
+        return Err(QueryError::NotLoaded);
+    }
+    Ok(properties["Unit"].clone())
+}
+
+/// Where a timer is in its lifecycle, derived from systemd's `ActiveState` and `SubState`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum TimerState {
+    /// Armed and waiting for the next elapse.
+    Waiting,
+    /// Elapsed and its service is currently running.
+    Running,
+    /// Elapsed with nothing left to trigger.
+    Elapsed,
+    /// Stopped.
+    Inactive,
+    /// The timer unit failed.
+    Failed,
+    /// Any other combination, as `(ActiveState, SubState)`.
+    Other(String,String),
+}
+
+impl TimerState {
+    fn from_states(active_state: &str, sub_state: &str) -> Self {
+        match (active_state, sub_state) {
+            (_, "waiting") => TimerState::Waiting,
+            (_, "running") => TimerState::Running,
+            (_, "elapsed") => TimerState::Elapsed,
+            ("inactive", "dead") => TimerState::Inactive,
+            ("failed", _) => TimerState::Failed,
+            _ => TimerState::Other(active_state.to_owned(),sub_state.to_owned()),
+        }
+    }
+}
+
+/// Current status of a registered timer.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct TimerStatus {
+    /// Lifecycle state.
+    pub state: TimerState,
+    /// Next time the timer will fire, if it's going to.
+    pub next_elapse: Option<NaiveDateTime>,
+}
+
+/// Returns whether the timer is still pending, has already fired, or failed.
+pub fn query_status<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<TimerStatus,QueryError> {
+    query_status_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_status()`], but against the service manager selected by `scope`.
+pub fn query_status_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<TimerStatus,QueryError> {
+    query_status_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "query_status", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn query_status_with(systemd: Systemd, unit_name: UnitName) -> Result<TimerStatus,QueryError> {
+    debug!("querying status");
+
+    let properties = extract_properties(systemd,unit_name,&["LoadState","ActiveState","SubState","NextElapseUSecRealtime"])?;
+    if properties["LoadState"] != "loaded" {
+        return Err(QueryError::NotLoaded);
+    }
+
+    Ok(TimerStatus {
+        state: TimerState::from_states(&properties["ActiveState"],&properties["SubState"]),
+        next_elapse: parse_timestamp(&properties["NextElapseUSecRealtime"]),
+    })
+}
+
+/// How the most recent run of a scheduled command ended.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct LastExit {
+    /// systemd's verdict on the service, e.g. `success` or `exit-code`.
+    pub result: String,
+    /// Exit status of the command, if it has exited.
+    pub status: Option<i32>,
+    /// When the command exited, if it has.
+    pub exited: Option<NaiveDateTime>,
+}
+
+/// Reads how the command behind a timer last exited, so callers can tell whether scheduled work
+/// actually succeeded. A failed service sticks around until it's deregistered, but systemd may
+/// unload a service that succeeded once its timer is done with it, which gives
+/// [`QueryError::NotLoaded`].
+pub fn query_last_exit<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<LastExit,QueryError> {
+    query_last_exit_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_last_exit()`], but against the service manager selected by `scope`.
+pub fn query_last_exit_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<LastExit,QueryError> {
+    query_last_exit_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "query_last_exit", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn query_last_exit_with(systemd: Systemd, unit_name: UnitName) -> Result<LastExit,QueryError> {
+    debug!("querying last exit");
+
+    let properties = extract_unit_properties(systemd,&unit_name.service_unit(),&["LoadState","Result","ExecMainStatus","ExecMainExitTimestamp"])?;
+    if properties["LoadState"] != "loaded" {
+        return Err(QueryError::NotLoaded);
+    }
+
+    Ok(LastExit::from_properties(&properties))
+}
+
+impl LastExit {
+    fn from_properties(properties: &BTreeMap<String,String>) -> Self {
+        let exited = parse_timestamp(property(properties,"ExecMainExitTimestamp"));
+        LastExit {
+            result: property(properties,"Result").to_owned(),
+            status: exited.and_then(|_| property(properties,"ExecMainStatus").parse().ok()),
+            exited,
+        }
+    }
+}
+
+/// systemd's verdict on the most recent run of a scheduled command, from the service's `Result`
+/// and `ExecMainStatus` properties.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum ServiceResult {
+    /// The command hasn't finished a run yet.
+    NotRun,
+    /// The command exited successfully.
+    Success,
+    /// The command exited with this non-zero code.
+    ExitCode(i32),
+    /// The command was killed by this signal.
+    Signal(i32),
+    /// The command was killed by this signal and dumped core.
+    CoreDump(i32),
+    /// The command ran for too long.
+    Timeout,
+    /// The command missed its watchdog deadline.
+    Watchdog,
+    /// The command was killed for running out of memory.
+    OomKill,
+    /// The command was started too often in too short a time.
+    StartLimitHit,
+    /// systemd couldn't set up the command's resources, e.g. a working directory that doesn't
+    /// exist.
+    Resources,
+    /// Any other `Result` value.
+    Other(String),
+}
+
+impl ServiceResult {
+    /// Whether the run failed. A command that hasn't run yet hasn't failed.
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, ServiceResult::NotRun | ServiceResult::Success)
+    }
+
+    fn from_last_exit(last_exit: &LastExit) -> Self {
+        let status = last_exit.status.unwrap_or_default();
+        match last_exit.result.as_str() {
+            "success" if last_exit.exited.is_none() => ServiceResult::NotRun,
+            "success" => ServiceResult::Success,
+            "exit-code" => ServiceResult::ExitCode(status),
+            "signal" => ServiceResult::Signal(status),
+            "core-dump" => ServiceResult::CoreDump(status),
+            "timeout" => ServiceResult::Timeout,
+            "watchdog" => ServiceResult::Watchdog,
+            "oom-kill" => ServiceResult::OomKill,
+            "start-limit-hit" => ServiceResult::StartLimitHit,
+            "resources" => ServiceResult::Resources,
+            other => ServiceResult::Other(other.to_owned()),
+        }
+    }
+}
+
+impl Display for ServiceResult {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ServiceResult::NotRun => write!(f,"not run yet"),
+            ServiceResult::Success => write!(f,"succeeded"),
+            ServiceResult::ExitCode(code) => write!(f,"failed (exit {})",code),
+            ServiceResult::Signal(signal) => write!(f,"killed by signal {}",signal),
+            ServiceResult::CoreDump(signal) => write!(f,"dumped core on signal {}",signal),
+            ServiceResult::Timeout => write!(f,"timed out"),
+            ServiceResult::Watchdog => write!(f,"missed its watchdog"),
+            ServiceResult::OomKill => write!(f,"killed for running out of memory"),
+            ServiceResult::StartLimitHit => write!(f,"hit the start limit"),
+            ServiceResult::Resources => write!(f,"failed to set up resources"),
+            ServiceResult::Other(result) => write!(f,"failed ({})",result),
+        }
+    }
+}
+
+/// Returns how the most recent run of the command behind a timer went, e.g. to show "last backup
+/// failed (exit 1)". Same caveats as [`query_last_exit()`].
+pub fn query_result<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<ServiceResult,QueryError> {
+    query_result_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_result()`], but against the service manager selected by `scope`.
+pub fn query_result_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<ServiceResult,QueryError> {
+    Ok(ServiceResult::from_last_exit(&query_last_exit_scoped(unit_name,scope)?))
+}
+
+/// Returns the longest the command behind a timer may run before systemd kills it, as set with
+/// [`RegisterOptions::runtime_max`]. `None` if there's no limit.
+pub fn query_runtime_max<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<Option<Duration>,QueryError> {
+    query_runtime_max_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`query_runtime_max()`], but against the service manager selected by `scope`.
+pub fn query_runtime_max_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<Option<Duration>,QueryError> {
+    query_runtime_max_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "query_runtime_max", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn query_runtime_max_with(systemd: Systemd, unit_name: UnitName) -> Result<Option<Duration>,QueryError> {
+    let properties = extract_unit_properties(systemd,&unit_name.service_unit(),&["LoadState","RuntimeMaxUSec"])?;
+    if properties["LoadState"] != "loaded" {
+        return Err(QueryError::NotLoaded);
+    }
+    match properties["RuntimeMaxUSec"].as_str() {
+        "" | "infinity" => Ok(None),
+        runtime_max => runtime_max.parse::<SystemdDuration>().map(|runtime_max| Some(runtime_max.into())).map_err(|_| QueryError::ParseError),
+    }
+}
+
+/// What [`wait_until_elapsed()`] saw once the timer had fired.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct TimerResult {
+    /// Lifecycle state of the timer after the run.
+    pub state: TimerState,
+    /// How the command exited. `None` if systemd already unloaded the service, which it may do
+    /// once a successful run is over.
+    pub last_exit: Option<LastExit>,
+}
+
+/// How often [`wait_until_elapsed()`] checks on the timer.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Blocks until the timer has fired and its command has finished, or `timeout` runs out, in
+/// which case it fails with [`QueryError::TimedOut`]. Meant for tests and simple scripts, it just
+/// polls systemd every so often.
+///
+/// A run counts if it finished after the wait began, or if the timer has elapsed for good.
+pub fn wait_until_elapsed<'a>(unit_name: impl Into<UnitName<'a>>, timeout: Duration) -> Result<TimerResult,QueryError> {
+    wait_until_elapsed_scoped(unit_name,timeout,Scope::User)
+}
+
+/// Same as [`wait_until_elapsed()`], but against the service manager selected by `scope`.
+pub fn wait_until_elapsed_scoped<'a>(unit_name: impl Into<UnitName<'a>>, timeout: Duration, scope: Scope) -> Result<TimerResult,QueryError> {
+    wait_until_elapsed_with(Systemd::new(scope),unit_name.into(),timeout,WAIT_POLL_INTERVAL)
+}
+
+#[instrument(name = "wait_until_elapsed", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn wait_until_elapsed_with(systemd: Systemd, unit_name: UnitName, timeout: Duration, interval: Duration) -> Result<TimerResult,QueryError> {
+    debug!("waiting for timer to elapse");
+
+    let deadline = Instant::now() + timeout;
+    // exit timestamps only have whole seconds
+    let since = Local::now().naive_local().with_nanosecond(0).unwrap_or_default();
+    let timer_name = unit_name.timer_unit();
+    let service_name = unit_name.service_unit();
+    loop {
+        let mut units = show_units(systemd,&[&timer_name,&service_name],&["LoadState","ActiveState","SubState","Result","ExecMainStatus","ExecMainExitTimestamp"])?.into_iter();
+        let timer = units.next().unwrap_or_default();
+        let service = units.next().unwrap_or_default();
+        if property(&timer,"LoadState") != "loaded" {
+            return Err(QueryError::NotLoaded);
+        }
+
+        let state = TimerState::from_states(property(&timer,"ActiveState"),property(&timer,"SubState"));
+        let last_exit = (property(&service,"LoadState") == "loaded").then(|| LastExit::from_properties(&service));
+        let ran = last_exit.as_ref().and_then(|last_exit| last_exit.exited).is_some_and(|exited| exited >= since);
+        if state != TimerState::Running && (ran || state == TimerState::Elapsed) {
+            return Ok(TimerResult { state, last_exit });
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(QueryError::TimedOut);
+        }
+        std::thread::sleep(interval.min(deadline - now));
+    }
+}
+
+/// Returns a ready-to-run `journalctl` command that shows what the command behind a timer logged.
+/// Add arguments like `--follow` or `--since=today` before running it.
+pub fn journal_command<'a>(unit_name: impl Into<UnitName<'a>>) -> Command {
+    journal_command_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`journal_command()`], but reads the journal of the service manager selected by
+/// `scope`.
+pub fn journal_command_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Command {
+    let mut command = Systemd::new(scope).command("journalctl");
+    command.arg(format!("--unit={}",unit_name.into().service_unit()));
+    command
+}
+
+/// Something that happened to the command behind a timer, as reported by a [`Watcher`].
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum UnitEvent {
+    /// The timer fired and systemd started the command.
+    Started,
+    /// A line the command logged.
+    Output(String),
+    /// The command finished, and how it went.
+    Finished(ServiceResult),
+}
+
+/// Follows the journal for what happens to the command behind a timer, so apps can react the
+/// moment it fires instead of polling [`query_status()`]. Only sees events from after it was
+/// started, and blocks until the next one arrives. Stops `journalctl` when dropped.
+///
+/// Relies on systemd's structured log messages, so it needs read access to the journal: a user
+/// manager's journal isn't kept on machines without persistent journal storage.
+///
+/// ```no_run
+/// # use systemd_wake::*;
+/// for event in watch(UnitName::new("reminder").unwrap()).unwrap() {
+///     if let UnitEvent::Finished(result) = event.unwrap() {
+///         println!("reminder {}",result);
+///         break;
+///     }
+/// }
+/// ```
+pub fn watch<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<Watcher,QueryError> {
+    watch_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`watch()`], but follows the journal of the service manager selected by `scope`.
+pub fn watch_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<Watcher,QueryError> {
+    check_platform()?;
+    let unit_name = unit_name.into();
+    let mut command = journal_command_scoped(unit_name,scope);
+    command.args(["--follow","--lines=0","--output=json"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    let mut child = command.spawn().map_err(|e| spawn_error(&command,e))?;
+    let stdout = child.stdout.take().map(std::io::BufReader::new);
+    Ok(Watcher {
+        child,
+        stdout,
+        unit_name: unit_name.into(),
+        status: None,
+    })
+}
+
+/// Iterator over [`UnitEvent`]s, returned by [`watch()`]. Ends if `journalctl` exits, after
+/// yielding its error if it failed.
+pub struct Watcher {
+    child: std::process::Child,
+    stdout: Option<std::io::BufReader<std::process::ChildStdout>>,
+    unit_name: UnitNameBuf,
+    /// Exit status of the last process exit seen, for the result message that follows it.
+    status: Option<i32>,
+}
+
+impl Watcher {
+    fn finish(&mut self) -> Option<Result<UnitEvent,QueryError>> {
+        use std::io::Read;
+
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        match self.child.wait() {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(Err(CommandError::CommandFailed { status, stdout: String::new(), stderr }.into())),
+            Err(e) => Some(Err(CommandError::RunCommand(e).into())),
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<UnitEvent,QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use std::io::BufRead;
+
+        loop {
+            let stdout = self.stdout.as_mut()?;
+            let mut line = String::new();
+            match stdout.read_line(&mut line) {
+                Ok(0) => {
+                    self.stdout = None;
+                    return self.finish();
+                },
+                Ok(_) => (),
+                Err(e) => return Some(Err(CommandError::RunCommand(e).into())),
+            }
+            match parse_journal_entry(&line,self.unit_name.as_unit_name(),&mut self.status) {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Turns one line of `journalctl --output=json` into an event, going by the message IDs the
+/// service manager tags its unit messages with (see `sd-messages.h`). Lines from the command
+/// carry the unit name as their syslog identifier.
+fn parse_journal_entry(line: &str, unit_name: UnitName, status: &mut Option<i32>) -> Result<Option<UnitEvent>,QueryError> {
+    const UNIT_STARTED: &str = "39f53479d3a045ac8e11786248231fbf";
+    const UNIT_SUCCESS: &str = "7ad2d189f7e94e70a38c781354912448";
+    const UNIT_RESULT: &str = "1f4e0a44a88649939aaea34fc6da8c95";
+    const UNIT_PROCESS_EXIT: &str = "98e322203f7a4ed290d09fe03c09fe15";
+
+    let entry: serde_json::Map<String,serde_json::Value> = serde_json::from_str(line).map_err(|_| QueryError::ParseError)?;
+    // fields are strings, or arrays of bytes when they aren't valid UTF-8
+    let field = |key: &str| match entry.get(key) {
+        Some(serde_json::Value::String(value)) => Some(value.clone()),
+        Some(serde_json::Value::Array(bytes)) => {
+            let bytes: Vec<u8> = bytes.iter().filter_map(|byte| byte.as_u64().map(|byte| byte as u8)).collect();
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        },
+        _ => None,
+    };
+
+    let event = match field("MESSAGE_ID").as_deref() {
+        Some(UNIT_STARTED) => Some(UnitEvent::Started),
+        Some(UNIT_SUCCESS) => Some(UnitEvent::Finished(ServiceResult::Success)),
+        Some(UNIT_PROCESS_EXIT) => {
+            *status = field("EXIT_STATUS").and_then(|status| status.parse().ok());
+            None
+        },
+        Some(UNIT_RESULT) => {
+            let last_exit = LastExit {
+                result: field("UNIT_RESULT").unwrap_or_default(),
+                status: status.take(),
+                exited: None,
+            };
+            Some(UnitEvent::Finished(ServiceResult::from_last_exit(&last_exit)))
+        },
+        Some(_) => None,
+        None if field("SYSLOG_IDENTIFIER").as_deref() == Some(unit_name.as_str()) => field("MESSAGE").map(UnitEvent::Output),
+        None => None,
+    };
+    Ok(event)
+}
+
+/// Returns every property systemd reports for the unit's timer, for debugging registrations that
+/// misbehave or attaching to bug reports. The timer doesn't have to be loaded, systemd reports
+/// what it knows either way.
+pub fn dump_properties<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<BTreeMap<String,String>,QueryError> {
+    dump_properties_scoped(unit_name,Scope::User)
+}
+
+/// Same as [`dump_properties()`], but against the service manager selected by `scope`.
+pub fn dump_properties_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<BTreeMap<String,String>,QueryError> {
+    dump_properties_with(Systemd::new(scope),unit_name.into())
+}
+
+#[instrument(name = "dump_properties", level = "debug", skip_all, fields(unit_name = %unit_name))]
+fn dump_properties_with(systemd: Systemd, unit_name: UnitName) -> Result<BTreeMap<String,String>,QueryError> {
+    Ok(show_units(systemd,&[&unit_name.timer_unit()],&[])?.pop().unwrap_or_default())
+}
+
+/// Parses a timestamp as printed by `systemctl show`, either `Thu 2023-04-13 10:00:00 CEST` (in
+/// local time) or `@1681372800`. Empty and `n/a` values mean "never".
+fn parse_timestamp(timestamp: &str) -> Option<NaiveDateTime> {
+    let timestamp = timestamp.trim();
+    if let Some(secs) = timestamp.strip_prefix('@') {
+        return from_usec(secs.parse::<i64>().ok()?.checked_mul(1_000_000)?);
+    }
+    let mut parts = timestamp.split_whitespace();
+    let mut date = parts.next()?;
+    if !date.contains('-') {
+        // skip the weekday
+        date = parts.next()?;
+    }
+    let time = parts.next()?;
+    NaiveDateTime::parse_from_str(&format!("{} {}",date,time),"%Y-%m-%d %H:%M:%S").ok()
+}
+
+/// Decodes the command from the service's `Environment`, falling back to the timer description
+/// that older versions stored it in.
+fn decode_registration(environment: &str, description: &str) -> Result<CommandConfig,QueryError> {
+    Ok(CommandConfig::decode_config(registration_payload(environment,description)?)?)
+}
+
+fn registration_payload(environment: &str, description: &str) -> Result<String,QueryError> {
+    match environment_value(environment,COMMAND_ENV_VAR) {
+        Some(encoded_command) => Ok(encoded_command),
+        None => description_payload(description),
+    }
+}
+
+/// Looks up a variable in an `Environment` property, which systemd prints as space separated
+/// `KEY=value` pairs, double quoting (and backslash escaping) any pair that needs it.
+fn environment_value(environment: &str, key: &str) -> Option<String> {
+    split_environment(environment).into_iter().find_map(|pair| {
+        Some(pair.strip_prefix(key)?.strip_prefix('=')?.to_owned())
+    })
+}
+
+fn split_environment(environment: &str) -> Vec<String> {
+    let mut pairs = Vec::new();
+    let mut chars = environment.chars();
+    let mut current = String::new();
+    let mut in_pair = false;
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_pair = true;
+            },
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            },
+            c if c.is_whitespace() && !quoted => {
+                if in_pair {
+                    pairs.push(std::mem::take(&mut current));
+                    in_pair = false;
+                }
+            },
+            c => {
+                current.push(c);
+                in_pair = true;
+            },
+        }
+    }
+    if in_pair {
+        pairs.push(current);
+    }
+    pairs
+}
+
+/// Only trusts the description if it decodes, since without the environment variable there's
+/// nothing else to tell a legacy registration from a unit created outside this crate.
+fn description_payload(desc: &str) -> Result<String,QueryError> {
+    // the helper path may contain spaces, but the encoded command never does
+    match desc.rsplit_once(' ') {
+        Some((_, encoded_command)) if CommandConfig::decode_config(encoded_command).is_ok() => Ok(encoded_command.to_owned()),
+        _ => Err(QueryError::NotOwned),
+    }
+}
+
+/// A timer found by [`list_registrations()`].
+#[derive(Clone,Debug,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct TimerInfo {
+    /// Unit name the timer was registered under.
+    pub unit_name: UnitNameBuf,
+    /// Next time the timer will fire, if it's going to.
+    pub next_elapse: Option<NaiveDateTime>,
+    /// Last time the timer fired, if it ever has.
+    pub last_trigger: Option<NaiveDateTime>,
+    /// Lifecycle state.
+    pub state: TimerState,
+}
+
+/// Lists the timers registered through this crate with their elapse times and state.
+///
+/// Timers are recognised by the encoded command systemd-wake leaves in their service's
+/// environment, so timers created by other programs are skipped.
+pub fn list_registrations() -> Result<Vec<TimerInfo>,QueryError> {
+    list_registrations_scoped(Scope::User)
+}
+
+/// Same as [`list_registrations()`], but against the service manager selected by `scope`.
+pub fn list_registrations_scoped(scope: Scope) -> Result<Vec<TimerInfo>,QueryError> {
+    list_registrations_with(Systemd::new(scope))
+}
+
+#[instrument(name = "list_registrations", level = "debug", skip_all)]
+fn list_registrations_with(systemd: Systemd) -> Result<Vec<TimerInfo>,QueryError> {
+    debug!("listing registrations");
+
+    let mut systemd_command = systemd.systemctl();
+    systemd_command
+        .arg("list-timers")
+        .arg("--all")
+        .arg("--output=json");
+
+    let output = systemd.run(systemd_command)?;
+    let timers = parse_timer_list(&output.stdout)?;
+    if timers.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let units: Vec<&str> = timers.iter()
+        .flat_map(|timer| [timer.unit.as_str(),timer.activates.as_str()])
+        .filter(|unit| !unit.is_empty())
+        .collect();
+    let properties = show_units(systemd,&units,&["Id","Description","Environment","ActiveState","SubState"])?;
+    let empty = BTreeMap::new();
+    let find = |unit: &str| properties.iter().find(|properties| property(properties,"Id") == unit).unwrap_or(&empty);
+
+    Ok(timers.into_iter().filter_map(|timer| {
+        let timer_properties = find(&timer.unit);
+        let service_properties = find(&timer.activates);
+        decode_registration(property(service_properties,"Environment"),property(timer_properties,"Description")).ok()?;
+        let unit_name = UnitNameBuf::new(timer.unit.strip_suffix(".timer").unwrap_or(&timer.unit)).ok()?;
+        Some(TimerInfo {
+            unit_name,
+            next_elapse: timer.next,
+            last_trigger: timer.last,
+            state: TimerState::from_states(property(timer_properties,"ActiveState"),property(timer_properties,"SubState")),
+        })
+    }).collect())
+}
+
+/// Counts the timers from [`list_registrations()`] that are still waiting to fire, optionally only
+/// those whose name starts with `prefix`. Handy for badges and rate limits.
+pub fn count_registrations(prefix: Option<&str>) -> Result<usize,QueryError> {
+    count_registrations_scoped(prefix,Scope::User)
+}
+
+/// Same as [`count_registrations()`], but against the service manager selected by `scope`.
+pub fn count_registrations_scoped(prefix: Option<&str>, scope: Scope) -> Result<usize,QueryError> {
+    count_registrations_with(Systemd::new(scope),prefix)
+}
+
+#[instrument(name = "count_registrations", level = "debug", skip(systemd))]
+fn count_registrations_with(systemd: Systemd, prefix: Option<&str>) -> Result<usize,QueryError> {
+    Ok(list_registrations_with(systemd)?.iter()
+        .filter(|timer| timer.state == TimerState::Waiting)
+        .filter(|timer| match prefix {
+            Some(prefix) => timer.unit_name.as_str().starts_with(prefix),
+            None => true,
+        })
+        .count())
+}
+
+/// One entry of `systemctl list-timers --output=json`.
+struct ListedTimer {
+    unit: String,
+    activates: String,
+    next: Option<NaiveDateTime>,
+    last: Option<NaiveDateTime>,
+}
+
+fn parse_timer_list(json: &[u8]) -> Result<Vec<ListedTimer>,QueryError> {
+    let timers: Vec<serde_json::Value> = serde_json::from_slice(json).map_err(|_| QueryError::ParseError)?;
+    timers.into_iter().map(|timer| {
+        Ok(ListedTimer {
+            unit: timer["unit"].as_str().ok_or(QueryError::ParseError)?.to_owned(),
+            activates: timer["activates"].as_str().unwrap_or_default().to_owned(),
+            // "never" comes through as 0 or null
+            next: timer["next"].as_i64().and_then(from_usec),
+            last: timer["last"].as_i64().and_then(from_usec),
+        })
+    }).collect()
+}
+
+/// Converts systemd's microseconds-since-epoch timestamps to local time, treating 0 as "never".
+fn from_usec(usec: i64) -> Option<NaiveDateTime> {
+    if usec <= 0 {
+        return None;
+    }
+    let utc = DateTime::from_timestamp_micros(usec)?;
+    Some(utc.with_timezone(&Local).naive_local())
+}
+
+/// Runs `systemctl show` for several units at once and splits the output into one property map
+/// per unit. No properties means all of them.
+fn show_units(systemd: Systemd, units: &[&str], properties: &[&str]) -> Result<Vec<BTreeMap<String,String>>,QueryError> {
+    let output = systemd.run(show_command(systemd,units,properties))?;
+    Ok(parse_show(&output))
+}
+
+fn show_command(systemd: Systemd, units: &[&str], properties: &[&str]) -> Command {
+    let mut systemd_command = systemd.systemctl();
+    systemd_command
+        .arg("show")
+        .args(units);
+    if !properties.is_empty() {
+        systemd_command.arg(format!("--property={}",properties.join(",")));
+    }
+    systemd_command
+}
+
+fn parse_show(output: &Output) -> Vec<BTreeMap<String,String>> {
+    // a stray non-UTF-8 byte in, say, a description shouldn't sink the whole query
+    let string = String::from_utf8_lossy(&output.stdout);
+    string.split("\n\n").map(parse_properties).collect()
+}
+
+/// Value of a property from [`show_units()`], empty if systemd left it out.
+fn property<'p>(properties: &'p BTreeMap<String,String>, key: &str) -> &'p str {
+    properties.get(key).map_or("",String::as_str)
+}
+
+/// Parses `key=value` lines. Keys systemd repeats (one line per entry of a list property, like
+/// `TimersCalendar`) are joined with newlines.
+fn parse_properties(block: &str) -> BTreeMap<String,String> {
+    let mut properties: BTreeMap<String,String> = BTreeMap::new();
+    for line in block.lines() {
+        if let Some((key, value)) = line.split_once('=') {
+            properties.entry(key.to_owned())
+                .and_modify(|existing| {
+                    existing.push('\n');
+                    existing.push_str(value);
+                })
+                .or_insert_with(|| value.to_owned());
+        }
+    }
+    properties
+}
+
+/// Error struct for querying task registration.
+#[derive(Error,Debug)]
+pub enum QueryError {
+    /// Error sending command to systemd
+    #[error("systemd command error")]
+    Command(#[from] CommandError),
+    /// Provided unit name is not loaded
+    #[error("unit with provided name not loaded")]
+    NotLoaded,
+    /// Error parsing systemd output
+    #[error("error parsing systemd output")]
+    ParseError,
+    /// The timer exists but wasn't registered through this crate (or carries no command, like
+    /// one from [`register_activating()`])
+    #[error("unit wasn't registered by systemd-wake")]
+    NotOwned,
+    /// Error decoding command
+    #[error("error decoding command")]
+    DecodeError(#[from] CommandConfigError),
+    /// Gave up waiting on the timer
+    #[error("timed out waiting for the timer")]
+    TimedOut,
+}
+
+/// Error struct for running a command. Wraps running with a non-success exit status as an error variant.
+#[derive(Error,Debug)]
+pub enum CommandError {
+    /// Error running the command
+    #[error("error running command")]
+    RunCommand(#[from] std::io::Error),
+    /// The program couldn't be found. For `systemd-run` and `systemctl` this almost always means
+    /// systemd isn't installed (or isn't on `PATH`).
+    #[error("`{0}` not found, is it installed and on PATH?")]
+    BinaryNotFound(String),
+    /// systemd isn't available on this operating system.
+    #[error("systemd isn't available on {0}, systemd-wake only works on Linux")]
+    UnsupportedPlatform(&'static str),
+    /// Error talking to systemd over D-Bus.
+    #[cfg(feature = "dbus")]
+    #[error("D-Bus error: {0}")]
+    DBus(#[from] zbus::Error),
+    /// Command ran, but exited with failure status. Output streams are decoded lossily so the
+    /// error message can include what the command complained about.
+    #[error("command exited with failure status ({status}): {}", stderr.trim_end())]
+    CommandFailed {
+        /// Exit status of the command
+        status: ExitStatus,
+        /// Captured standard output
+        stdout: String,
+        /// Captured standard error
+        stderr: String,
+    },
+}
+
+impl CommandError {
+    /// Exit code of a command that ran and failed. `None` for other errors, and for a command
+    /// killed by a signal.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            CommandError::CommandFailed { status, .. } => status.code(),
+            _ => None,
+        }
+    }
+
+    /// What a command that ran and failed wrote to standard error.
+    pub fn stderr_string(&self) -> Option<String> {
+        match self {
+            CommandError::CommandFailed { stderr, .. } => Some(stderr.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Output> for CommandError {
+    fn from(output: Output) -> Self {
+        CommandError::CommandFailed {
+            status: output.status,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    }
+}
+
+/// Helper function for running commands.
+pub fn run_command(mut command: Command) -> Result<Output,CommandError> {
+    match command.output() {
+        Ok(output) => {
+            if output.status.success() {
+                Ok(output)
+            } else {
+                Err(output.into())
+            }
+        },
+        Err(e) => Err(spawn_error(&command,e)),
+    }
+}
+
+fn spawn_error(command: &Command, e: std::io::Error) -> CommandError {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        CommandError::BinaryNotFound(command.get_program().to_string_lossy().into_owned())
+    } else {
+        CommandError::RunCommand(e)
+    }
+}
+
+/// Which output stream a line passed to [`run_command_streaming()`] came from.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum OutputStream {
+    /// Standard output
+    Stdout,
+    /// Standard error
+    Stderr,
+}
+
+/// Like [`run_command()`], but hands each line of output to `on_line` as soon as the command
+/// writes it instead of buffering everything until it exits. Lines are decoded lossily and passed
+/// without their trailing newline.
+///
+/// On failure the returned [`CommandError::CommandFailed`] carries the command's standard error
+/// but not its standard output, which has already been passed to `on_line` and isn't kept around.
+pub fn run_command_streaming(mut command: Command, mut on_line: impl FnMut(OutputStream, &str)) -> Result<ExitStatus,CommandError> {
+    use std::io::BufRead;
+
+    let mut child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error(&command,e))?;
+
+    // read both pipes on their own threads so neither can fill up and stall the command, and
+    // funnel the lines back here so `on_line` doesn't have to be `Send`
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let readers: Vec<_> = [
+        child.stdout.take().map(|pipe| (OutputStream::Stdout,Box::new(pipe) as Box<dyn std::io::Read + Send>)),
+        child.stderr.take().map(|pipe| (OutputStream::Stderr,Box::new(pipe) as Box<dyn std::io::Read + Send>)),
+    ].into_iter().flatten().map(|(stream, pipe)| {
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            for line in std::io::BufReader::new(pipe).split(b'\n') {
+                let Ok(line) = line else { break };
+                if sender.send((stream,line)).is_err() {
+                    break;
+                }
+            }
+        })
+    }).collect();
+    drop(sender);
+
+    let mut stderr = String::new();
+    for (stream, line) in receiver {
+        let line = String::from_utf8_lossy(&line);
+        if stream == OutputStream::Stderr {
+            stderr.push_str(&line);
+            stderr.push('\n');
+        }
+        on_line(stream,&line);
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    let status = child.wait()?;
+    if status.success() {
+        Ok(status)
+    } else {
+        Err(CommandError::CommandFailed {
+            status,
+            stdout: String::new(),
+            stderr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Records commands instead of running them, answering each with the next canned
+    /// `(stdout, stderr)` pair.
+    struct MockRunner {
+        calls: std::cell::RefCell<Vec<Vec<String>>>,
+        replies: std::cell::RefCell<std::collections::VecDeque<(String,String)>>,
+    }
+
+    impl MockRunner {
+        fn new(replies: &[(&str, &str)]) -> Self {
+            MockRunner {
+                calls: Default::default(),
+                replies: std::cell::RefCell::new(replies.iter().map(|(stdout, stderr)| (stdout.to_string(),stderr.to_string())).collect()),
+            }
+        }
+
+        fn systemd(&self) -> Systemd<'_> {
+            Systemd {
+                scope: Scope::User,
+                runner: self,
+            }
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn run(&self, command: Command) -> Result<Output,CommandError> {
+            self.calls.borrow_mut().push(argv(&command));
+            let (stdout, stderr) = self.replies.borrow_mut().pop_front().unwrap_or_default();
+            Ok(Output {
+                status: ExitStatus::default(),
+                stdout: stdout.into_bytes(),
+                stderr: stderr.into_bytes(),
+            })
+        }
+    }
+
+    /// A daily registration with a fixed helper path, for checking what options add to the argv.
+    fn test_builder(name: &str) -> RegisterBuilder<'_> {
+        RegisterBuilder::new(UnitName::new(name).unwrap(),Command::new("true"))
+            .at(Schedule::Calendar("daily".to_owned()))
+            .helper("/opt/bin/systemd-wake")
+    }
+
+    #[test]
+    fn test_register_argv() {
+        let runner = MockRunner::new(&[
+            ("LoadState=not-found\n",""),
+            ("","Running timer as unit: test-mock.timer\nWill run service as unit: test-mock.service\n"),
+            ("NextElapseUSecRealtime=Thu 2023-04-13 10:00:00 CEST\n",""),
+        ]);
+        let options = RegisterOptions {
+            helper: Some("/opt/bin/systemd-wake".into()),
+            ..Default::default()
+        };
+        let unit_name = UnitName::new("test-mock").unwrap();
+        let (registration, _) = register_with(runner.systemd(),Schedule::After(Duration::from_secs(90)),unit_name,CommandConfig::encode(Command::new("true")).unwrap(),&options).unwrap();
+        assert_eq!(registration.timer_unit,"test-mock.timer");
+        assert_eq!(registration.next_elapse,NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%F %T").ok());
+
+        let calls = runner.calls.borrow();
+        assert_eq!(calls.len(),3);
+        assert_eq!(calls[0],["systemctl","--user","show","test-mock.timer","test-mock.service","--property=LoadState"]);
+        assert_eq!(calls[1][..4],["systemd-run","--user","--unit=test-mock","--description=systemd-wake test-mock"]);
+        assert!(calls[1][4].starts_with("--setenv=SYSTEMD_WAKE_COMMAND="));
+        assert_eq!(calls[1][5..],["--property=SyslogIdentifier=test-mock","--on-active=1min 30s","/opt/bin/systemd-wake"]);
+        assert_eq!(calls[2],["systemctl","--user","show","test-mock.timer","--property=NextElapseUSecRealtime"]);
+    }
+
+    #[test]
+    fn test_c_locale() {
+        let systemctl = Systemd::new(Scope::System).systemctl();
+        assert_eq!(argv(&systemctl),["systemctl","--system"]);
+        assert!(systemctl.get_envs().any(|(key, value)| key == "LC_ALL" && value == Some("C".as_ref())));
+    }
+
+    #[test]
+    fn test_register_activating() {
+        let runner = MockRunner::new(&[("LoadState=not-found\n",""),("","Running timer as unit: test-mock.timer\n")]);
+        let unit_name = UnitName::new("test-mock").unwrap();
+        let options = RegisterOptions {
+            persistent: true,
+            ..Default::default()
+        };
+        let (registration, _) = register_job(runner.systemd(),Schedule::Calendar("daily".to_owned()),unit_name,Job::Unit("backup.service"),&options).unwrap();
+        assert_eq!(registration.timer_unit,"test-mock.timer");
+        assert_eq!(registration.service_unit,"backup.service");
+        assert_eq!(runner.calls.borrow()[1],["systemd-run","--user","--unit=test-mock","--description=systemd-wake test-mock","--on-calendar=daily","--timer-property=Persistent=true","--timer-property=Unit=backup.service"]);
+
+        let options = RegisterOptions {
+            nice: Some(10),
+            ..Default::default()
+        };
+        assert!(matches!(register_command(runner.systemd(),&Schedule::Calendar("daily".to_owned()),unit_name,&Job::Unit("backup.service"),&options),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(register_command(runner.systemd(),&Schedule::Calendar("daily".to_owned()),unit_name,&Job::Unit("no such unit"),&RegisterOptions::default()),Err(RegistrationError::InvalidOption(_))));
+
+        let runner = MockRunner::new(&[("LoadState=loaded\nUnit=backup.service\n","")]);
+        assert_eq!(query_target_with(runner.systemd(),unit_name).unwrap(),"backup.service");
+    }
+
+    #[test]
+    fn test_wait_until_loaded() {
+        let runner = MockRunner::new(&[("LoadState=not-found\n",""),("LoadState=loaded\n","")]);
+        wait_until_loaded(runner.systemd(),UnitName::new("test-mock").unwrap(),Duration::from_secs(5),Duration::ZERO).unwrap();
+        assert_eq!(runner.calls.borrow().len(),2);
+
+        let runner = MockRunner::new(&[("LoadState=not-found\n","")]);
+        let error = wait_until_loaded(runner.systemd(),UnitName::new("test-mock").unwrap(),Duration::ZERO,Duration::ZERO).unwrap_err();
+        assert!(matches!(error,RegistrationError::NeverLoaded(_)));
+    }
+
+    #[test]
+    fn test_linger_check() {
+        let options = RegisterOptions {
+            helper: Some("/opt/bin/systemd-wake".into()),
+            linger_check: LingerCheck::Require,
+            ..Default::default()
+        };
+        let register = |runner: &MockRunner| register_with(runner.systemd(),Schedule::Calendar("daily".to_owned()),UnitName::new("test-mock").unwrap(),CommandConfig::encode(Command::new("true")).unwrap(),&options);
+        let runner = MockRunner::new(&[("Linger=no\n","")]);
+        assert!(matches!(register(&runner),Err(RegistrationError::NotLingering)));
+        let calls = runner.calls.borrow();
+        assert_eq!(calls.len(),1);
+        assert_eq!(calls[0][..2],["loginctl","show-user"]);
+        assert_eq!(calls[0][3],"--property=Linger");
+
+        let runner = MockRunner::new(&[("Linger=yes\n",""),("LoadState=not-found\n",""),("","Running timer as unit: test-mock.timer\n")]);
+        assert!(register(&runner).is_ok());
+    }
+
+    #[test]
+    fn test_register_duplicate() {
+        let runner = MockRunner::new(&[("LoadState=loaded\n","")]);
+        let unit_name = UnitName::new("test-mock").unwrap();
+        let result = register_with(runner.systemd(),Schedule::After(Duration::from_secs(90)),unit_name,CommandConfig::encode(Command::new("true")).unwrap(),&RegisterOptions::default());
+        assert!(matches!(result,Err(RegistrationError::Duplicate)));
+        assert_eq!(runner.calls.borrow().len(),1);
+    }
+
+    #[test]
+    fn test_replace_keeps_options() {
+        let query = format!("LoadState=loaded\nDescription=systemd-wake test-replace\nTimersCalendar={{ OnCalendar=daily ; next_elapse=n/a }}\n\nLoadState=loaded\nEnvironment={}={}\n",COMMAND_ENV_VAR,CommandConfig::encode(Command::new("true")).unwrap());
+        let registered = "Running timer as unit: test-replace.timer\nWill run service as unit: test-replace.service\n";
+        let options = RegisterOptions {
+            persistent: true,
+            helper: Some("/opt/bin/systemd-wake".into()),
+            ..Default::default()
+        };
+        let unit_name = UnitName::new("test-replace").unwrap();
+
+        // query, stop, reset-failed, name check, systemd-run, next elapse
+        let runner = MockRunner::new(&[(&query,""),("",""),("",""),("LoadState=not-found\n\nLoadState=not-found\n",""),("",registered),("","")]);
+        replace(runner.systemd(),unit_name,Schedule::Calendar("weekly".to_owned()),None,&options).unwrap();
+        let calls = runner.calls.borrow();
+        assert_eq!(calls[4][0],"systemd-run");
+        assert!(calls[4].contains(&"--on-calendar=weekly".to_owned()));
+        assert!(calls[4].contains(&"--timer-property=Persistent=true".to_owned()));
+
+        // a replacement in the past is refused, and the old timer comes back with the same options
+        let runner = MockRunner::new(&[(&query,""),("",""),("",""),("LoadState=not-found\n\nLoadState=not-found\n",""),("",registered),("","")]);
+        let past = Local::now().naive_local() - chrono::Duration::hours(1);
+        assert!(matches!(replace(runner.systemd(),unit_name,Schedule::At(past),None,&options),Err(RegistrationError::UpdateFailed { restored: true, .. })));
+        let calls = runner.calls.borrow();
+        assert!(calls[4].contains(&"--on-calendar=daily".to_owned()));
+        assert!(calls[4].contains(&"--timer-property=Persistent=true".to_owned()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_error() {
+        use std::os::unix::process::ExitStatusExt;
+        let failed = |stderr: &str| CommandError::from(Output {
+            status: ExitStatus::from_raw(256),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        });
+        assert!(matches!(run_error(failed("Failed to start transient timer unit: Unit test-mock.timer was already loaded or has a fragment file.\n")),RegistrationError::Duplicate));
+        match run_error(failed("Failed to start transient service unit: Unit test-mock.service was already loaded or has a fragment file.\n")) {
+            RegistrationError::NameConflict(unit) => assert_eq!(unit,"test-mock.service"),
+            other => panic!("expected NameConflict, got {:?}",other),
+        }
+        // older systemd
+        assert!(matches!(run_error(failed("Failed to start transient timer unit: Unit test-mock.timer already exists.\n")),RegistrationError::Duplicate));
+        match run_error(failed("Failed to start transient service unit: Unit test-mock.service already exists.\n")) {
+            RegistrationError::NameConflict(unit) => assert_eq!(unit,"test-mock.service"),
+            other => panic!("expected NameConflict, got {:?}",other),
+        }
+        let error = run_error(failed("Failed to start transient timer unit: Unit name invalid.\n"));
+        assert_eq!(error.to_string(),"error with registration command: Failed to start transient timer unit: Unit name invalid.");
+        assert_eq!(run_error(failed("")).to_string(),"error with registration command");
+        assert!(matches!(run_error(failed("Failed to connect to bus\n")),RegistrationError::Command(_)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_already_stopped() {
+        use std::os::unix::process::ExitStatusExt;
+        let failed = |stderr: &str| CommandError::from(Output {
+            status: ExitStatus::from_raw(5 << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        });
+        let output = already_stopped(failed("Failed to stop test-mock.timer: Unit test-mock.timer not loaded.\n")).unwrap();
+        assert_eq!(output.status.code(),Some(5));
+        assert!(already_stopped(failed("Failed to stop test-mock.timer: Access denied\n")).is_err());
+        assert!(already_stopped(CommandError::BinaryNotFound("systemctl".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_register_name_conflict() {
+        let runner = MockRunner::new(&[("LoadState=not-found\n\nLoadState=loaded\n","")]);
+        let unit_name = UnitName::new("test-mock").unwrap();
+        let result = register_with(runner.systemd(),Schedule::After(Duration::from_secs(90)),unit_name,CommandConfig::encode(Command::new("true")).unwrap(),&RegisterOptions::default());
+        match result {
+            Err(RegistrationError::NameConflict(unit)) => assert_eq!(unit,"test-mock.service"),
+            other => panic!("expected NameConflict, got {:?}",other),
+        }
+        assert_eq!(runner.calls.borrow().len(),1);
+    }
+
+    #[test]
+    fn test_deregister_argv() {
+        let encoded = CommandConfig::encode(Command::new("true")).unwrap();
+        let show = format!("LoadState=loaded\nDescription=systemd-wake test-mock\nTimersCalendar={{ OnCalendar=2023-04-13 10:00:00 ; next_elapse=n/a }}\n\nEnvironment={}={}\n",COMMAND_ENV_VAR,encoded);
+        let runner = MockRunner::new(&[(&show,"")]);
+        let unit_name = UnitName::new("test-mock").unwrap();
+        let (command, timer_spec, _) = deregister_with(runner.systemd(),unit_name).unwrap();
+        assert_eq!(command.get_program(),"true");
+        assert_eq!(timer_spec.waketime(),NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%F %T").ok());
+
+        let calls = runner.calls.borrow();
+        assert_eq!(calls.len(),3);
+        assert_eq!(calls[0],["systemctl","--user","show","test-mock.timer","test-mock.service","--property=LoadState,Description,TimersCalendar,TimersMonotonic,Environment"]);
+        assert_eq!(calls[1],["systemctl","--user","stop","test-mock.timer"]);
+        assert_eq!(calls[2],["systemctl","--user","reset-failed","test-mock.service","test-mock.timer"]);
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let encoded = CommandConfig::encode(Command::new("true")).unwrap();
+        let show = format!("LoadState=loaded\nDescription=systemd-wake test-mock\nTimersCalendar={{ OnCalendar=daily ; next_elapse=n/a }}\n\nEnvironment={}={}\n",COMMAND_ENV_VAR,encoded);
+        let runner = MockRunner::new(&[(&show,"")]);
+        let saved = pause_with(runner.systemd(),UnitName::new("test-mock").unwrap()).unwrap();
+        assert_eq!(saved.schedule,Schedule::Calendar("daily".to_owned()));
+        assert_eq!(saved.encoded_command,encoded);
+        assert_eq!(runner.calls.borrow()[1],["systemctl","--user","stop","test-mock.timer"]);
+
+        let runner = MockRunner::new(&[("LoadState=not-found\n",""),("","Running timer as unit: test-mock.timer\n")]);
+        let options = RegisterOptions {
+            helper: Some("/opt/bin/systemd-wake".into()),
+            ..Default::default()
+        };
+        let registration = resume_with(runner.systemd(),&saved,&options).unwrap();
+        assert_eq!(registration.unit_name,saved.unit_name);
+        let calls = runner.calls.borrow();
+        assert!(calls[1].contains(&format!("--setenv={}={}",COMMAND_ENV_VAR,encoded)));
+        assert!(calls[1].contains(&"--on-calendar=daily".to_owned()));
+
+        // counting from activation would start over
+        let show = format!("LoadState=loaded\nDescription=systemd-wake test-mock\nTimersMonotonic={{ OnActiveUSec=30min ; next_elapse=1h 2min }}\n\nEnvironment={}={}\n",COMMAND_ENV_VAR,encoded);
+        let runner = MockRunner::new(&[(&show,"")]);
+        assert!(matches!(pause_with(runner.systemd(),UnitName::new("test-mock").unwrap()),Err(RegistrationError::NotRecreatable)));
+        assert_eq!(runner.calls.borrow().len(),1);
+    }
+
+    #[test]
+    fn test_query_monotonic_timer() {
+        let encoded = CommandConfig::encode(Command::new("true")).unwrap();
+        let show = format!("LoadState=loaded\nDescription=systemd-wake test-mock\nTimersCalendar=\nTimersMonotonic={{ OnActiveUSec=30min ; next_elapse=1h 2min }}\n\nEnvironment={}={}\n",COMMAND_ENV_VAR,encoded);
+        let runner = MockRunner::new(&[(&show,"")]);
+        let (_, timer_spec) = query_registration_with(runner.systemd(),UnitName::new("test-mock").unwrap()).unwrap();
+        assert!(timer_spec.calendar.is_empty());
+        assert_eq!(timer_spec.monotonic,[MonotonicTrigger { base: MonotonicBase::Active, offset: Duration::from_secs(1800) }]);
+        assert_eq!(timer_spec.waketime(),None);
+    }
+
+    #[test]
+    fn test_registration_guard_leak() {
+        let registration = Registration {
+            unit_name: UnitNameBuf::new("test-guard").unwrap(),
+            timer_unit: "test-guard.timer".to_owned(),
+            service_unit: "test-guard.service".to_owned(),
+            schedule: Schedule::Calendar("daily".to_owned()),
+            next_elapse: None,
+            invocation_id: None,
+        };
+        let guard = RegistrationGuard {
+            registration: Some(registration.clone()),
+            scope: Scope::User,
+        };
+        assert_eq!(guard.registration(),&registration);
+        assert_eq!(guard.leak(),registration);
+    }
+
+    #[test]
+    fn test_query_foreign_timer() {
+        let show = "LoadState=loaded\nDescription=Daily backup\nTimersCalendar={ OnCalendar=daily ; next_elapse=n/a }\n\nEnvironment=PATH=/usr/bin\n";
+        let runner = MockRunner::new(&[(show,"")]);
+        assert!(matches!(query_registration_with(runner.systemd(),UnitName::new("test-mock").unwrap()),Err(QueryError::NotOwned)));
+        // a payload that's there but broken is a different problem
+        let show = format!("LoadState=loaded\nDescription=systemd-wake test-mock\n\nEnvironment={}=not-a-payload!\n",COMMAND_ENV_VAR);
+        let runner = MockRunner::new(&[(&show,"")]);
+        assert!(matches!(query_registration_with(runner.systemd(),UnitName::new("test-mock").unwrap()),Err(QueryError::DecodeError(_))));
+    }
+
+    #[test]
+    fn test_register_encoded() {
+        assert!(matches!(register_encoded(Schedule::After(Duration::from_secs(60)),UnitName::new("test-encoded").unwrap(),"not a payload!"),Err(RegistrationError::CommandConfig(_))));
+    }
+
+    #[test]
+    fn test_count_registrations() {
+        let list = r#"[{"next":0,"last":0,"unit":"myapp-a.timer","activates":"myapp-a.service"},{"next":0,"last":0,"unit":"myapp-b.timer","activates":"myapp-b.service"},{"next":0,"last":0,"unit":"other.timer","activates":"other.service"}]"#;
+        let environment = format!("Environment={}={}",COMMAND_ENV_VAR,CommandConfig::encode(Command::new("true")).unwrap());
+        let show = format!("Id=myapp-a.timer\nActiveState=active\nSubState=waiting\n\nId=myapp-a.service\n{0}\n\nId=myapp-b.timer\nActiveState=active\nSubState=elapsed\n\nId=myapp-b.service\n{0}\n\nId=other.timer\nActiveState=active\nSubState=waiting\n\nId=other.service\n{0}\n",environment);
+        let runner = MockRunner::new(&[(list,""),(&show,""),(list,""),(&show,"")]);
+        assert_eq!(count_registrations_with(runner.systemd(),Some("myapp-")).unwrap(),1);
+        assert_eq!(count_registrations_with(runner.systemd(),None).unwrap(),2);
+    }
+
+    #[test]
+    fn test_deregister_prefix() {
+        let list = r#"[{"next":0,"last":0,"unit":"myapp-a.timer","activates":"myapp-a.service"},{"next":0,"last":0,"unit":"other.timer","activates":"other.service"}]"#;
+        let environment = format!("Environment={}={}",COMMAND_ENV_VAR,CommandConfig::encode(Command::new("true")).unwrap());
+        let show = format!("Id=myapp-a.timer\n\nId=myapp-a.service\n{}\n\nId=other.timer\n\nId=other.service\n{}\n",environment,environment);
+        let runner = MockRunner::new(&[(list,""),(&show,"")]);
+        let results = deregister_prefix_with(runner.systemd(),"myapp-").unwrap();
+        assert_eq!(results.len(),1);
+        assert_eq!(results[0].0.as_ref(),"myapp-a");
+        assert!(results[0].1.is_ok());
+
+        let calls = runner.calls.borrow();
+        assert_eq!(calls.len(),4);
+        assert_eq!(calls[2],["systemctl","--user","stop","myapp-a.timer"]);
+    }
+
+    #[test]
+    fn test_service_result() {
+        let last_exit = |result: &str, status: Option<i32>, exited: bool| LastExit {
+            result: result.to_owned(),
+            status,
+            exited: exited.then(|| Local::now().naive_local()),
+        };
+        assert_eq!(ServiceResult::from_last_exit(&last_exit("success",None,false)),ServiceResult::NotRun);
+        assert_eq!(ServiceResult::from_last_exit(&last_exit("success",Some(0),true)),ServiceResult::Success);
+        let failed = ServiceResult::from_last_exit(&last_exit("exit-code",Some(1),true));
+        assert_eq!(failed,ServiceResult::ExitCode(1));
+        assert_eq!(failed.to_string(),"failed (exit 1)");
+        assert!(failed.is_failure());
+        assert_eq!(ServiceResult::from_last_exit(&last_exit("signal",Some(9),true)),ServiceResult::Signal(9));
+        assert_eq!(ServiceResult::from_last_exit(&last_exit("oom-kill",Some(9),true)),ServiceResult::OomKill);
+        assert_eq!(ServiceResult::from_last_exit(&last_exit("protocol",None,true)),ServiceResult::Other("protocol".to_owned()));
+    }
+
+    #[test]
+    fn test_wait_until_elapsed() {
+        let exited = (Local::now() + chrono::Duration::seconds(5)).format("%a %F %T %Z").to_string();
+        let waiting = "LoadState=loaded\nActiveState=active\nSubState=waiting\n\nLoadState=loaded\nResult=success\n".to_owned();
+        let elapsed = format!("LoadState=loaded\nActiveState=active\nSubState=elapsed\n\nLoadState=loaded\nResult=exit-code\nExecMainStatus=1\nExecMainExitTimestamp={}\n",exited);
+        let runner = MockRunner::new(&[(&waiting,""),(&elapsed,"")]);
+        let unit_name = UnitName::new("test-mock").unwrap();
+        let result = wait_until_elapsed_with(runner.systemd(),unit_name,Duration::from_secs(5),Duration::ZERO).unwrap();
+        assert_eq!(result.state,TimerState::Elapsed);
+        let last_exit = result.last_exit.unwrap();
+        assert_eq!(last_exit.result,"exit-code");
+        assert_eq!(last_exit.status,Some(1));
+        assert_eq!(runner.calls.borrow().len(),2);
+
+        let runner = MockRunner::new(&[(&waiting,"")]);
+        assert!(matches!(wait_until_elapsed_with(runner.systemd(),unit_name,Duration::ZERO,Duration::ZERO),Err(QueryError::TimedOut)));
+    }
+
+    #[test]
+    #[ignore = "needs a live systemd user session and the play binary"]
+    fn test_beep() {
+        // one minute in the future
+        let waketime = chrono::Local::now().naive_local() + chrono::Duration::minutes(1);
+
+        // schedule a short beep
+        let mut command = std::process::Command::new("play");
+        command.args(vec!["-q","-n","synth","0.1","sin","880"]);
+
+        // create unit handle
+        let unit_name = UnitName::new("my-special-unit-name-123").unwrap();
+
+        // register future beep
+        register(waketime,unit_name,command).unwrap();
+
+        // check future beep
+        let (_command, _datetime) = query_registration(unit_name).unwrap();
+
+        // cancel future beep
+        let (_command, _datetime) = deregister(unit_name).unwrap();
+    }
+
+    #[test]
+    fn test_unit_name_validation() {
+        assert!(UnitName::new("my-special_unit:name.123").is_ok());
+        assert!(matches!(UnitName::new(""),Err(UnitNameError::Empty)));
+        assert!(matches!(UnitName::new("foo bar"),Err(UnitNameError::ContainsWhitespace)));
+        assert!(matches!(UnitName::new("foo/bar"),Err(UnitNameError::InvalidCharacter('/'))));
+        assert!(matches!(UnitName::new("foo;rm"),Err(UnitNameError::InvalidCharacter(';'))));
+        let long = "a".repeat(UnitName::MAX_LEN + 1);
+        assert!(matches!(UnitName::new(&long),Err(UnitNameError::TooLong)));
+    }
+
+    #[test]
+    fn test_unit_name_suffix() {
+        for input in ["foo","foo.timer","foo.service"] {
+            let name = UnitName::new(input).unwrap();
+            assert_eq!(name.as_ref(),"foo");
+            assert_eq!(name.timer_unit(),"foo.timer");
+            assert_eq!(name.service_unit(),"foo.service");
+            assert_eq!(UnitNameBuf::new(input).unwrap().as_ref(),"foo");
+            assert_eq!(UnitName::sanitize(input).as_ref(),"foo");
+        }
+        assert_eq!(UnitName::new("foo.bar").unwrap().timer_unit(),"foo.bar.timer");
+        assert!(matches!(UnitName::new(".timer"),Err(UnitNameError::Empty)));
+        assert_eq!(UnitName::sanitize(".service").as_ref(),"unnamed");
+    }
+
+    #[test]
+    fn test_unit_name_round_trip() {
+        let show = format!("LoadState=loaded\nDescription=systemd-wake test-mock\nTimersCalendar={{ OnCalendar=daily ; next_elapse=n/a }}\n\nEnvironment={}={}\n",COMMAND_ENV_VAR,CommandConfig::encode(Command::new("true")).unwrap());
+        let runner = MockRunner::new(&[
+            ("LoadState=not-found\n",""),
+            ("","Running timer as unit: test-mock.timer\n"),
+            ("",""),
+            (&show,""),
+        ]);
+        let options = RegisterOptions {
+            helper: Some("/opt/bin/systemd-wake".into()),
+            ..Default::default()
+        };
+        let input = "test-mock.timer";
+        let unit_name = UnitName::new(input).unwrap();
+        assert_eq!(unit_name.as_str(),"test-mock");
+        let (registration, _) = register_with(runner.systemd(),Schedule::Calendar("daily".to_owned()),unit_name,CommandConfig::encode(Command::new("true")).unwrap(),&options).unwrap();
+        assert_eq!(registration.unit_name.as_str(),unit_name.as_str());
+        assert_eq!(registration.timer_unit,unit_name.timer_unit());
+        query_registration_with(runner.systemd(),registration.unit_name.as_unit_name()).unwrap();
+
+        let calls = runner.calls.borrow();
+        assert_eq!(calls[1][2],"--unit=test-mock");
+        assert_eq!(calls[3][3],"test-mock.timer");
+    }
+
+    #[test]
+    fn test_unit_name_buf() {
+        let buf = UnitNameBuf::new(format!("reminder-{}",42)).unwrap();
+        let name: UnitName = (&buf).into();
+        assert_eq!(name.as_ref(),"reminder-42");
+        assert_eq!(name.timer_unit(),"reminder-42.timer");
+        assert_eq!(name.service_unit(),"reminder-42.service");
+        assert_eq!(UnitNameBuf::from(name),buf);
+        assert!(UnitNameBuf::new("foo/bar".to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_unit_name_map_keys() {
+        let name = UnitName::new("reminder-1").unwrap();
+        let mut borrowed = std::collections::HashMap::new();
+        borrowed.insert(name,1);
+        assert_eq!(borrowed.get("reminder-1"),Some(&1));
+
+        let mut owned = BTreeMap::new();
+        owned.insert(UnitNameBuf::from(&name),1);
+        owned.insert(UnitNameBuf::new("reminder-0").unwrap(),0);
+        assert_eq!(owned.get("reminder-1"),Some(&1));
+        assert_eq!(owned.keys().next().unwrap().as_ref(),"reminder-0");
+        assert_eq!(UnitName::from(&name),name);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_unit_name_serde() {
+        let buf = UnitNameBuf::new("reminder-1").unwrap();
+        assert_eq!(serde_json::to_string(&buf).unwrap(),r#""reminder-1""#);
+        assert_eq!(serde_json::from_str::<UnitNameBuf>(r#""reminder-1""#).unwrap(),buf);
+        assert!(serde_json::from_str::<UnitNameBuf>(r#""foo/bar""#).is_err());
+        assert_eq!(serde_json::from_str::<UnitName>(r#""reminder-1""#).unwrap(),buf.as_unit_name());
+        assert_eq!(serde_json::to_string(&buf.as_unit_name()).unwrap(),r#""reminder-1""#);
+    }
+
+    #[test]
+    fn test_namespace() {
+        let namespace = Namespace::new("myapp").unwrap();
+        assert_eq!(namespace.name("reminder-1").unwrap().as_ref(),"myapp-reminder-1");
+        assert!(matches!(namespace.name("foo/bar"),Err(UnitNameError::InvalidCharacter('/'))));
+        assert!(matches!(namespace.name(&"a".repeat(UnitName::MAX_LEN)),Err(UnitNameError::TooLong)));
+        assert_eq!(namespace.strip("myapp-reminder-1"),Some("reminder-1"));
+        assert_eq!(namespace.strip("myappx-reminder-1"),None);
+        assert!(Namespace::new("my app").is_err());
+        assert!(matches!(Namespace::new("a".repeat(UnitName::MAX_LEN - 1)),Err(UnitNameError::TooLong)));
+    }
+
+    #[test]
+    fn test_unit_name_generate() {
+        let time = NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%F %T").unwrap();
+        let mut command = Command::new("/usr/bin/notify-send.sh");
+        command.arg("hello");
+        let first = UnitName::generate(&command,&time);
+        let second = UnitName::generate(&command,&time);
+        assert_ne!(first,second);
+        for name in [first,second,UnitName::generate(&Command::new("/"),&time)] {
+            assert_eq!(UnitNameBuf::new(name.as_str()).unwrap(),name);
+        }
+        assert!(UnitName::generate(&command,&time).as_str().starts_with("notify-send-sh-20230413T100000-"));
+        assert!(UnitName::generate(&Command::new("/"),&time).as_str().starts_with("command-"));
+    }
+
+    #[test]
+    fn test_unit_name_sanitize() {
+        assert_eq!(UnitName::sanitize("Buy milk, eggs & bread!").as_ref(),"Buy-milk--eggs---bread-");
+        assert_eq!(UnitName::sanitize("café ☕").as_ref(),"caf---");
+        assert_eq!(UnitName::sanitize("").as_ref(),"unnamed");
+        assert_eq!(UnitName::sanitize(&"x".repeat(1000)).as_ref().len(),UnitName::MAX_LEN);
+        for input in ["a b","ünïcødé","tab\there",&"é".repeat(300)] {
+            let sanitized = UnitName::sanitize(input);
+            assert!(UnitName::new(sanitized.as_ref()).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_monotonic_schedules() {
+        let five_minutes = Duration::from_secs(300);
+        assert_eq!(Schedule::After(five_minutes).timer_args(),["--on-active=5min"]);
+        assert_eq!(Schedule::OnBoot(five_minutes).timer_args(),["--on-boot=5min"]);
+        assert_eq!(Schedule::OnStartup(five_minutes).timer_args(),["--on-startup=5min"]);
+        assert_eq!(Schedule::OnBoot(five_minutes).to_string(),"5min after boot");
+        assert_eq!(Schedule::Every(five_minutes).timer_args(),["--on-active=5min","--on-unit-active=5min"]);
+        assert_eq!(Schedule::Every(five_minutes).to_string(),"every 5min");
+    }
+
+    #[test]
+    fn test_parse_calendar() {
+        let datetime = NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(Schedule::parse_calendar("2023-04-13 10:00:00"),Schedule::At(datetime));
+        assert_eq!(Schedule::parse_calendar("Mon *-*-* 08:00:00"),Schedule::Calendar("Mon *-*-* 08:00:00".to_owned()));
+    }
+
+    #[test]
+    fn test_date_schedule() {
+        let date = NaiveDate::from_ymd_opt(2023,4,13).unwrap();
+        let schedule = Schedule::from(date);
+        assert_eq!(schedule.timer_args(),["--on-calendar=2023-04-13"]);
+        assert_eq!(schedule.waketime(),date.and_hms_opt(0,0,0));
+        assert!(matches!(check_waketime(&schedule,&RegisterOptions::default()),Err(RegistrationError::TimeInPast(_))));
+    }
+
+    #[test]
+    fn test_calendar_formatting() {
+        let datetime = NaiveDateTime::parse_from_str("2023-04-13 09:30:00.25","%F %T%.f").unwrap();
+        assert_eq!(Schedule::At(datetime).timer_args(),["--on-calendar=2023-04-13 09:30:00.250000"]);
+        assert_eq!(Schedule::parse_calendar("2023-04-13 09:30:00.250000"),Schedule::At(datetime));
+        assert_eq!(Schedule::At(datetime.with_nanosecond(0).unwrap()).timer_args(),["--on-calendar=2023-04-13 09:30:00"]);
+        assert_eq!(Schedule::formatted(datetime,"%a *-*-* %H:%M:00"),Schedule::Calendar("Thu *-*-* 09:30:00".to_owned()));
+        let nine = chrono::NaiveTime::from_hms_opt(9,0,0).unwrap();
+        assert_eq!(Schedule::weekly(chrono::Weekday::Mon,nine),Schedule::Calendar("Mon *-*-* 09:00:00".to_owned()));
+    }
+
+    #[test]
+    fn test_timezone_aware_schedule() {
+        let utc = Utc.with_ymd_and_hms(2023,4,13,10,0,0).unwrap();
+        let offset = utc.with_timezone(&chrono::FixedOffset::east_opt(2 * 3600).unwrap());
+        let schedule = Schedule::from(offset);
+        assert_eq!(schedule,Schedule::Calendar("2023-04-13 10:00:00 UTC".to_owned()));
+        assert_eq!(Schedule::parse_calendar("2023-04-13 10:00:00 UTC"),Schedule::At(utc.with_timezone(&Local).naive_local()));
+    }
+
+    #[test]
+    fn test_offset_waketime() {
+        let base = Utc.with_ymd_and_hms(2023,4,13,10,0,0).unwrap();
+        let expected = (base + chrono::Duration::minutes(10)).with_timezone(&Local).naive_local();
+        assert_eq!(offset_waketime(base,Duration::from_secs(600)).unwrap(),expected);
+        assert!(matches!(offset_waketime(base,Duration::MAX),Err(RegistrationError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_time_in_past() {
+        let waketime = Local::now().naive_local() - chrono::Duration::minutes(1);
+        let command = Command::new("true");
+        match register(waketime,UnitName::new("test-in-past").unwrap(),command) {
+            Err(RegistrationError::TimeInPast(time)) => assert_eq!(time,waketime),
+            other => panic!("expected TimeInPast, got {:?}", other),
+        }
+        for past_tolerance in [Duration::MAX,Duration::from_secs(u64::MAX),Duration::from_secs(400_000 * 365 * 86_400)] {
+            let options = RegisterOptions {
+                past_tolerance,
+                ..Default::default()
+            };
+            assert!(check_waketime(&Schedule::At(waketime),&options).is_ok());
+        }
+        assert_eq!(Schedule::After(Duration::from_secs(5)).waketime(),None);
+        assert_eq!(Schedule::Calendar("2023-04-13 10:00:00".to_owned()).waketime(),Some(NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%F %T").unwrap()));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut command = Command::new("echo");
+        command.arg("hello world");
+        let argv = RegisterBuilder::new(UnitName::new("test-dry-run").unwrap(),command)
+            .at(Schedule::Calendar("daily".to_owned()))
+            .description("Say hello")
+            .persistent(true)
+            .remain_after_elapse(false)
+            .on_failure("notify-failure.service")
+            .helper("/opt/bin/systemd-wake")
+            .dry_run()
+            .unwrap();
+        assert_eq!(argv[..4],["systemd-run","--user","--unit=test-dry-run","--description=Say hello"]);
+        assert!(argv[4].starts_with("--setenv=SYSTEMD_WAKE_COMMAND="));
+        assert_eq!(argv[5..],["--property=SyslogIdentifier=test-dry-run","--on-calendar=daily","--timer-property=Persistent=true","--timer-property=RemainAfterElapse=false","--property=OnFailure=notify-failure.service","/opt/bin/systemd-wake"]);
+    }
+
+    #[test]
+    fn test_parse_timer_list() {
+        let json = br#"[{"next":1681380000000000,"left":1000,"last":null,"passed":null,"unit":"a.timer","activates":"a.service"},{"next":0,"left":0,"last":1681372800000000,"passed":0,"unit":"b.timer","activates":"b.service"}]"#;
+        let timers = parse_timer_list(json).unwrap();
+        assert_eq!(timers.len(),2);
+        assert_eq!(timers[0].unit,"a.timer");
+        assert_eq!(timers[0].activates,"a.service");
+        assert_eq!(timers[0].next,from_usec(1681380000000000));
+        assert!(timers[0].next.is_some());
+        assert_eq!(timers[0].last,None);
+        assert_eq!(timers[1].next,None);
+        assert_eq!(timers[1].last,from_usec(1681372800000000));
+        assert!(parse_timer_list(b"[]").unwrap().is_empty());
+        assert!(parse_timer_list(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_extra_properties() {
+        let argv = test_builder("test-extra").property("MemoryMax=1G").timer_property("WakeSystem=true").dry_run().unwrap();
+        assert!(argv.contains(&"--property=MemoryMax=1G".to_owned()));
+        assert!(argv.contains(&"--timer-property=WakeSystem=true".to_owned()));
+
+        assert!(matches!(test_builder("test-extra").property("MemoryMax").dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-extra").property("ExecStart=/bin/sh").dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-extra").timer_property("OnCalendar=weekly").dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-extra").persistent(true).timer_property("Persistent=false").dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(test_builder("test-extra").timer_property("Persistent=true").dry_run().is_ok());
+    }
+
+    #[test]
+    fn test_program_not_found() {
+        let builder = || RegisterBuilder {
+            command: Command::new("systemd-wake-test-no-such-binary").into(),
+            ..test_builder("test-missing")
+        };
+        match builder().dry_run() {
+            Err(RegistrationError::ProgramNotFound(program)) => assert_eq!(program,"systemd-wake-test-no-such-binary"),
+            other => panic!("expected ProgramNotFound, got {:?}",other),
+        }
+        assert!(builder().allow_missing_program(true).dry_run().is_ok());
+    }
+
+    #[test]
+    fn test_service_environment() {
+        let argv = test_builder("test-env").env("PATH","/opt/bin:/usr/bin").dry_run().unwrap();
+        assert!(argv.contains(&"--setenv=PATH=/opt/bin:/usr/bin".to_owned()));
+        assert!(matches!(test_builder("test-env").env(COMMAND_ENV_VAR,"x").dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-env").env("A=B","x").dry_run(),Err(RegistrationError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_priority_options() {
+        let argv = test_builder("test-priority").nice(15).io_class(IoClass::Idle).dry_run().unwrap();
+        assert!(argv.contains(&"--property=Nice=15".to_owned()));
+        assert!(argv.contains(&"--property=IOSchedulingClass=idle".to_owned()));
+        assert!(matches!(test_builder("test-priority").nice(20).dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-priority").nice(5).property("Nice=1").dry_run(),Err(RegistrationError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_supervision_options() {
+        let argv = test_builder("test-supervised").restart(RestartPolicy::OnFailure).restart_delay(Duration::from_secs(5)).watchdog(Duration::from_secs(30)).dry_run().unwrap();
+        assert!(argv.contains(&"--property=Restart=on-failure".to_owned()));
+        assert!(argv.contains(&"--property=RestartSec=5s".to_owned()));
+        assert!(argv.contains(&"--property=WatchdogSec=30s".to_owned()));
+        assert!(matches!(test_builder("test-supervised").watchdog(Duration::ZERO).dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-supervised").restart_delay(Duration::from_secs(5)).dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-supervised").restart(RestartPolicy::Always).property("Restart=no").dry_run(),Err(RegistrationError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_run_as_user() {
+        let argv = test_builder("test-user").scope(Scope::System).user("backup").group("backup").dry_run().unwrap();
+        assert_eq!(argv[1],"--system");
+        assert!(argv.contains(&"--property=User=backup".to_owned()));
+        assert!(argv.contains(&"--property=Group=backup".to_owned()));
+        assert!(matches!(test_builder("test-user").user("backup").dry_run(),Err(RegistrationError::InvalidOption(_))));
+        assert!(matches!(test_builder("test-user").scope(Scope::System).group("").dry_run(),Err(RegistrationError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_slice() {
+        let argv = test_builder("test-slice").slice("myapp-jobs").dry_run().unwrap();
+        assert!(argv.contains(&"--slice=myapp-jobs.slice".to_owned()));
+        assert!(test_builder("test-slice").slice("myapp.slice").dry_run().unwrap().contains(&"--slice=myapp.slice".to_owned()));
+        for slice in ["","-myapp","myapp-","my--app","my/app"] {
+            assert!(matches!(test_builder("test-slice").slice(slice).dry_run(),Err(RegistrationError::InvalidOption(_))),"{}",slice);
+        }
+        assert!(matches!(test_builder("test-slice").slice("myapp").property("Slice=other.slice").dry_run(),Err(RegistrationError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_runtime_max() {
+        let argv = test_builder("test-runtime").runtime_max(Duration::from_secs(90)).dry_run().unwrap();
+        assert!(argv.contains(&"--property=RuntimeMaxSec=1min 30s".to_owned()));
+        assert!(matches!(test_builder("test-runtime").runtime_max(Duration::ZERO).dry_run(),Err(RegistrationError::InvalidOption(_))));
+
+        let unit_name = UnitName::new("test-runtime").unwrap();
+        let runner = MockRunner::new(&[("LoadState=loaded\nRuntimeMaxUSec=1min 30s\n",""),("LoadState=loaded\nRuntimeMaxUSec=infinity\n",""),("LoadState=not-found\n","")]);
+        assert_eq!(query_runtime_max_with(runner.systemd(),unit_name).unwrap(),Some(Duration::from_secs(90)));
+        assert_eq!(query_runtime_max_with(runner.systemd(),unit_name).unwrap(),None);
+        assert!(matches!(query_runtime_max_with(runner.systemd(),unit_name),Err(QueryError::NotLoaded)));
+        assert_eq!(runner.calls.borrow()[0],["systemctl","--user","show","test-runtime.service","--property=LoadState,RuntimeMaxUSec"]);
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let argv = test_builder("test-limits").memory_max(512 << 20).cpu_quota(50).tasks_max(16).dry_run().unwrap();
+        assert!(argv.contains(&"--property=MemoryMax=512M".to_owned()));
+        assert!(argv.contains(&"--property=CPUQuota=50%".to_owned()));
+        assert!(argv.contains(&"--property=TasksMax=16".to_owned()));
+        assert!(matches!(test_builder("test-limits").cpu_quota(0).dry_run(),Err(RegistrationError::InvalidOption(_))));
+
+        assert_eq!(format_bytes(1 << 30),"1G");
+        assert_eq!(format_bytes(1536 << 10),"1536K");
+        assert_eq!(format_bytes(1000),"1000");
+    }
+
+    #[test]
+    fn test_parse_journal_entry() {
+        let unit_name = UnitName::new("test-watch").unwrap();
+        let mut status = None;
+        let mut parse = |line: &str| parse_journal_entry(line,unit_name,&mut status).unwrap();
+        assert_eq!(parse(r#"{"MESSAGE_ID":"39f53479d3a045ac8e11786248231fbf","MESSAGE":"Started test-watch.service."}"#),Some(UnitEvent::Started));
+        assert_eq!(parse(r#"{"SYSLOG_IDENTIFIER":"test-watch","MESSAGE":"hello"}"#),Some(UnitEvent::Output("hello".to_owned())));
+        assert_eq!(parse(r#"{"SYSLOG_IDENTIFIER":"test-watch","MESSAGE":[104,105]}"#),Some(UnitEvent::Output("hi".to_owned())));
+        assert_eq!(parse(r#"{"SYSLOG_IDENTIFIER":"systemd","MESSAGE":"Reloading."}"#),None);
+        assert_eq!(parse(r#"{"MESSAGE_ID":"98e322203f7a4ed290d09fe03c09fe15","EXIT_CODE":"exited","EXIT_STATUS":"3"}"#),None);
+        assert_eq!(parse(r#"{"MESSAGE_ID":"1f4e0a44a88649939aaea34fc6da8c95","UNIT_RESULT":"exit-code"}"#),Some(UnitEvent::Finished(ServiceResult::ExitCode(3))));
+        assert_eq!(parse(r#"{"MESSAGE_ID":"7ad2d189f7e94e70a38c781354912448"}"#),Some(UnitEvent::Finished(ServiceResult::Success)));
+        assert!(matches!(parse_journal_entry("not json",unit_name,&mut None),Err(QueryError::ParseError)));
+    }
+
+    #[test]
+    fn test_dump_properties() {
+        let runner = MockRunner::new(&[("Id=test-mock.timer\nLoadState=loaded\nUnit=test-mock.service\n","")]);
+        let properties = dump_properties_with(runner.systemd(),UnitName::new("test-mock").unwrap()).unwrap();
+        assert_eq!(properties.len(),3);
+        assert_eq!(properties["Unit"],"test-mock.service");
+        assert_eq!(runner.calls.borrow()[0],["systemctl","--user","show","test-mock.timer"]);
+    }
+
+    #[test]
+    fn test_parse_properties() {
+        let properties = parse_properties("LoadState=loaded\nDescription=systemd-wake abc=\nTimersCalendar={ a }\nTimersCalendar={ b }\n");
+        assert_eq!(properties["LoadState"],"loaded");
+        assert_eq!(properties["Description"],"systemd-wake abc=");
+        assert_eq!(properties["TimersCalendar"],"{ a }\n{ b }");
+    }
+
+    #[test]
+    fn test_environment_value() {
+        let environment = "FOO=bar SYSTEMD_WAKE_COMMAND=abc SYSTEMD_WAKE_COMMAND_X=def";
+        assert_eq!(environment_value(environment,COMMAND_ENV_VAR).as_deref(),Some("abc"));
+        assert_eq!(environment_value(environment,"FOO").as_deref(),Some("bar"));
+        assert_eq!(environment_value(environment,"BAZ"),None);
+        assert_eq!(environment_value("",COMMAND_ENV_VAR),None);
+
+        let quoted = r#""GREETING=hello SYSTEMD_WAKE_COMMAND=nope" SYSTEMD_WAKE_COMMAND=abc "SAY=\"hi\"""#;
+        assert_eq!(split_environment(quoted),["GREETING=hello SYSTEMD_WAKE_COMMAND=nope","SYSTEMD_WAKE_COMMAND=abc","SAY=\"hi\""]);
+        assert_eq!(environment_value(quoted,COMMAND_ENV_VAR).as_deref(),Some("abc"));
+    }
+
+    #[test]
+    fn test_registration_channel_round_trip() {
+        let command = || {
+            let mut command = Command::new("printf");
+            command.args(["%s\n","hello world","it's \"quoted\"","ünïcødé ✓"]).env("GREETING","hi there");
+            CommandConfig::from(command)
+        };
+        for format in [Format::Json,Format::Binary] {
+            let encoded = CommandConfig::encode_with(command(),format).unwrap();
+            // surrounded by variables systemd has to quote
+            let environment = format!(r#""GREETING=hi there" {}={} "SAY=\"hi\"""#,COMMAND_ENV_VAR,encoded);
+            let decoded = decode_registration(&environment,"systemd-wake test").unwrap();
+            assert_eq!(decoded.args(),command().args());
+            assert_eq!(decoded.env_vars(),command().env_vars());
+            // timers from before the environment channel
+            let decoded = decode_registration("",&format!("/home/some user/.cargo/bin/systemd-wake {}",encoded)).unwrap();
+            assert_eq!(decoded.args(),command().args());
+        }
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let datetime = NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%Y-%m-%d %H:%M:%S").ok();
+        assert_eq!(parse_timestamp("Thu 2023-04-13 10:00:00 CEST"),datetime);
+        assert_eq!(parse_timestamp("2023-04-13 10:00:00"),datetime);
+        assert_eq!(parse_timestamp(""),None);
+        assert_eq!(parse_timestamp("n/a"),None);
+        assert!(parse_timestamp("@1681372800").is_some());
+    }
+
+    #[test]
+    fn test_parse_timers_calendar() {
+        let single = TimerSpec::parse("{ OnCalendar=2023-04-13 10:00:00 ; next_elapse=Thu 2023-04-13 10:00:00 CEST }","");
+        assert_eq!(single.waketime(),NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%Y-%m-%d %H:%M:%S").ok());
+
+        let multiple = TimerSpec::parse("{ OnCalendar=Mon *-*-* 08:00:00 ; next_elapse=n/a }\nTimersCalendar={ OnCalendar=*-*-* *:00/15:00 ; next_elapse=n/a }","");
+        assert_eq!(multiple.calendar,vec![
+            Schedule::Calendar("Mon *-*-* 08:00:00".to_owned()),
+            Schedule::Calendar("*-*-* *:00/15:00".to_owned()),
+        ]);
+        assert_eq!(multiple.waketime(),None);
+
+        assert_eq!(TimerSpec::parse("",""),TimerSpec::default());
+    }
+
+    #[test]
+    fn test_parse_timers_monotonic() {
+        let spec = TimerSpec::parse("","{ OnActiveUSec=30min ; next_elapse=1h 2min }\n{ OnBootUSec=1d 2h ; next_elapse=0 }");
+        assert!(spec.calendar.is_empty());
+        assert_eq!(spec.monotonic,vec![
+            MonotonicTrigger { base: MonotonicBase::Active, offset: Duration::from_secs(1800) },
+            MonotonicTrigger { base: MonotonicBase::Boot, offset: Duration::from_secs(93_600) },
+        ]);
+    }
+
+    #[test]
+    fn test_timer_spec_schedule() {
+        let at = Schedule::At(NaiveDateTime::parse_from_str("2023-04-13 10:00:00","%F %T").unwrap());
+        let spec = TimerSpec { calendar: vec![at.clone()], monotonic: vec![] };
+        assert_eq!(spec.schedule(),Some(at));
+        let boot = MonotonicTrigger { base: MonotonicBase::Boot, offset: Duration::from_secs(60) };
+        let spec = TimerSpec { calendar: vec![], monotonic: vec![boot] };
+        assert_eq!(spec.schedule(),Some(Schedule::OnBoot(Duration::from_secs(60))));
+        let active = MonotonicTrigger { base: MonotonicBase::Active, offset: Duration::from_secs(60) };
+        let spec = TimerSpec { calendar: vec![], monotonic: vec![active] };
+        assert_eq!(spec.schedule(),None);
+        let unit_active = MonotonicTrigger { base: MonotonicBase::UnitActive, offset: Duration::from_secs(60) };
+        let spec = TimerSpec { calendar: vec![], monotonic: vec![active,unit_active] };
+        assert_eq!(spec.schedule(),Some(Schedule::Every(Duration::from_secs(60))));
+    }
+
+    #[test]
+    fn test_registration_from_output() {
+        let output = Output {
+            status: ExitStatus::default(),
+            stdout: Vec::new(),
+            stderr: b"Running timer as unit: foo.timer\nWill run service as unit: foo.service\n".to_vec(),
+        };
+        let unit_name = UnitName::new("foo").unwrap();
+        let registration = Registration::from_output(unit_name,Schedule::Calendar("daily".to_owned()),&output);
+        assert_eq!(registration.timer_unit,"foo.timer");
+        assert_eq!(registration.service_unit,"foo.service");
+        assert_eq!(registration.invocation_id,None);
+
+        let output = Output {
+            stderr: b"Running timer as unit: foo.timer; invocation ID: 6c7b3d0ae4b44c4f8e4bd5d1b5f1d3a2\nWill run service as unit: foo.service\n".to_vec(),
+            ..output
+        };
+        let registration = Registration::from_output(unit_name,Schedule::Calendar("daily".to_owned()),&output);
+        assert_eq!(registration.timer_unit,"foo.timer");
+        assert_eq!(registration.invocation_id.as_deref(),Some("6c7b3d0ae4b44c4f8e4bd5d1b5f1d3a2"));
+        assert_eq!(argv(&journal_command(unit_name)),["journalctl","--user","--unit=foo.service"]);
+    }
+
+    #[test]
+    fn test_binary_not_found() {
+        match run_command_streaming(Command::new("systemd-wake-test-no-such-binary"),|_, _| ()) {
+            Err(CommandError::BinaryNotFound(program)) => assert_eq!(program,"systemd-wake-test-no-such-binary"),
+            other => panic!("expected BinaryNotFound, got {:?}",other),
+        }
+        match run_command(Command::new("systemd-wake-test-no-such-binary")) {
+            Err(CommandError::BinaryNotFound(program)) => assert_eq!(program,"systemd-wake-test-no-such-binary"),
+            other => panic!("expected BinaryNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_command_streaming() {
+        let mut lines = Vec::new();
+        let mut command = Command::new("sh");
+        command.args(["-c","echo one; echo two >&2; echo three; exit 3"]);
+        match run_command_streaming(command,|stream, line| lines.push((stream,line.to_owned()))) {
+            Err(CommandError::CommandFailed { status, stdout, stderr }) => {
+                assert_eq!(status.code(),Some(3));
+                assert!(stdout.is_empty());
+                assert_eq!(stderr,"two\n");
+            },
+            other => panic!("expected CommandFailed, got {:?}",other),
+        }
+        let stdout: Vec<_> = lines.iter().filter(|(stream, _)| *stream == OutputStream::Stdout).map(|(_, line)| line.as_str()).collect();
+        assert_eq!(stdout,["one","three"]);
+        assert!(lines.contains(&(OutputStream::Stderr,"two".to_owned())));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_command_failed_message() {
+        use std::os::unix::process::ExitStatusExt;
+        let error: CommandError = Output {
+            status: ExitStatus::from_raw(256),
+            stdout: Vec::new(),
+            stderr: b"Unit foo.timer not loaded.\n".to_vec(),
+        }.into();
+        assert_eq!(error.to_string(),"command exited with failure status (exit status: 1): Unit foo.timer not loaded.");
+        assert_eq!(error.exit_code(),Some(1));
+        assert_eq!(error.stderr_string().as_deref(),Some("Unit foo.timer not loaded.\n"));
+        let error = CommandError::BinaryNotFound("systemctl".to_owned());
+        assert_eq!(error.exit_code(),None);
+        assert_eq!(error.stderr_string(),None);
+    }
+
+    #[test]
+    fn test_check_platform() {
+        assert_eq!(check_platform().is_ok(),cfg!(target_os = "linux"));
+        let error = CommandError::UnsupportedPlatform("macos");
+        assert_eq!(error.to_string(),"systemd isn't available on macos, systemd-wake only works on Linux");
     }
 }
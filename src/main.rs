@@ -1,7 +1,71 @@
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+// same conventions as env(1) and friends, so these don't get mistaken for the command's own codes
+/// The encoded command couldn't be read.
+const EXIT_BAD_COMMAND: i32 = 125;
+/// The command was found but couldn't be started.
+const EXIT_CANNOT_RUN: i32 = 126;
+/// The command wasn't found.
+const EXIT_NOT_FOUND: i32 = 127;
+
 fn main() {
-    if std::env::args().len() > 1 {
-        let encoded_command = std::env::args().nth(1).unwrap();
-        let command = systemd_wake::command::CommandConfig::decode(encoded_command).unwrap();
-        _ = systemd_wake::run_command(command);
+    // the command normally comes in through the environment, but older registrations pass it as
+    // the first argument
+    let encoded_command = match std::env::var(systemd_wake::COMMAND_ENV_VAR) {
+        Ok(encoded_command) => Some(encoded_command),
+        Err(_) => std::env::args().nth(1),
+    };
+    // a timer that wakes up to nothing should show up in the journal, not pass silently
+    let Some(encoded_command) = encoded_command else {
+        eprintln!("no command given, expected it in {} or as the first argument",systemd_wake::COMMAND_ENV_VAR);
+        std::process::exit(EXIT_BAD_COMMAND);
+    };
+    // keep it out of the command's environment
+    std::env::remove_var(systemd_wake::COMMAND_ENV_VAR);
+    let config = match systemd_wake::command::CommandConfig::decode_config(encoded_command) {
+        Ok(config) => config,
+        Err(e) => {
+            // the top level error alone rarely says what's wrong with the payload
+            let mut message = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(e) = source {
+                message.push_str(&format!(": {}",e));
+                source = e.source();
+            }
+            eprintln!("failed to decode command: {}",message);
+            std::process::exit(EXIT_BAD_COMMAND);
+        },
+    };
+    // any step of a chain may be the one that failed, so name them all
+    let program = config.programs().iter().map(|program| program.to_string_lossy()).collect::<Vec<_>>().join(" && ");
+    // stdout and stderr go straight to the journal, so log the outcome alongside them
+    match config.status() {
+        Ok(status) => {
+            eprintln!("{} exited with {}",program,status);
+            std::process::exit(exit_code(status));
+        },
+        Err(e) => {
+            eprintln!("failed to run {}: {}",program,e);
+            let code = match e.kind() {
+                std::io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+                _ => EXIT_CANNOT_RUN,
+            };
+            std::process::exit(code);
+        },
     }
 }
+
+/// Passes the command's exit code through, so systemd marks the service failed exactly when the
+/// command failed. A command killed by a signal gets 128 + the signal number, like in a shell.
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    if let Some(signal) = status.signal() {
+        return 128 + signal;
+    }
+    1
+}
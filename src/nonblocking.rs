@@ -0,0 +1,151 @@
+//! Async versions of [`register()`](crate::register), [`deregister()`](crate::deregister) and
+//! [`query_registration()`](crate::query_registration).
+//!
+//! These run `systemd-run` and `systemctl` with [`tokio::process::Command`], so they have to be
+//! awaited inside a tokio runtime. They build the same invocations as the blocking API and read
+//! the output the same way, so scheduling many timers concurrently no longer serializes on the
+//! calling task and doesn't tie up a thread per call.
+
+use std::process::{Command,Output};
+
+use tracing::{debug,instrument};
+
+use crate::command::CommandConfig;
+use crate::{CommandError,QueryError,Registration,RegistrationError,RegisterOptions,Schedule,Scope,Systemd,TimerSpec,UnitName};
+
+/// Async version of [`register()`](crate::register).
+pub async fn register<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>) -> Result<Registration,RegistrationError> {
+    register_with_options(schedule,unit_name,command,&RegisterOptions::default()).await
+}
+
+/// Async version of [`register_scoped()`](crate::register_scoped).
+pub async fn register_scoped<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, scope: Scope) -> Result<Registration,RegistrationError> {
+    let options = RegisterOptions {
+        scope,
+        ..Default::default()
+    };
+    register_with_options(schedule,unit_name,command,&options).await
+}
+
+/// Async version of [`register_with_options()`](crate::register_with_options).
+pub async fn register_with_options<'a>(schedule: impl Into<Schedule>, unit_name: impl Into<UnitName<'a>>, command: impl Into<CommandConfig>, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    let encoded_command = crate::encode_command(command,options)?;
+    register_with(options.scope,schedule.into(),unit_name.into(),&encoded_command,options).await
+}
+
+#[instrument(name = "register", level = "debug", skip_all, fields(unit_name = %unit_name))]
+async fn register_with(scope: Scope, schedule: Schedule, unit_name: UnitName<'_>, encoded_command: &str, options: &RegisterOptions) -> Result<Registration,RegistrationError> {
+    debug!("registering timer");
+
+    crate::check_waketime(&schedule,options)?;
+
+    if crate::needs_linger_check(Systemd::new(scope),options) {
+        let lingering = crate::lingering(run(crate::linger_command(Systemd::new(scope))?).await)?;
+        crate::check_lingering(options,lingering)?;
+    }
+
+    let units = show_units(scope,&[&unit_name.timer_unit(),&unit_name.service_unit()],&["LoadState"]).await?;
+    crate::name_free(unit_name,units)?;
+
+    let systemd_command = crate::register_command(Systemd::new(scope),&schedule,unit_name,&crate::Job::Command(encoded_command),options)?;
+
+    debug!("running timer command: {:?}",systemd_command);
+    let output = run(systemd_command).await.map_err(crate::run_error)?;
+    let mut registration = Registration::from_output(unit_name,schedule,&output);
+    debug!("registered {} -> {}",registration.timer_unit,registration.service_unit);
+
+    // the timer exists by now, so don't fail the registration over this
+    match show_units(scope,&[&registration.timer_unit],&["NextElapseUSecRealtime"]).await {
+        Ok(units) => registration.next_elapse = units.first().and_then(|properties| crate::parse_timestamp(crate::property(properties,"NextElapseUSecRealtime"))),
+        Err(e) => debug!("couldn't read next elapse: {}",e),
+    }
+    Ok(registration)
+}
+
+/// Async version of [`deregister()`](crate::deregister).
+pub async fn deregister<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(Command,TimerSpec),RegistrationError> {
+    deregister_scoped(unit_name,Scope::User).await
+}
+
+/// Async version of [`deregister_scoped()`](crate::deregister_scoped).
+pub async fn deregister_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(Command,TimerSpec),RegistrationError> {
+    deregister_with(scope,unit_name.into()).await
+}
+
+#[instrument(name = "deregister", level = "debug", skip_all, fields(unit_name = %unit_name))]
+async fn deregister_with(scope: Scope, unit_name: UnitName<'_>) -> Result<(Command,TimerSpec),RegistrationError> {
+    let (config, timer_spec) = query_with(scope,unit_name).await?;
+
+    debug!("deregistering timer");
+    let systemd_command = crate::stop_command(Systemd::new(scope),unit_name);
+    debug!("running stop timer command: {:?}",systemd_command);
+    run(systemd_command).await.or_else(crate::already_stopped)?;
+
+    let systemd_command = crate::reset_failed_command(Systemd::new(scope),unit_name);
+    debug!("running reset-failed command: {:?}",systemd_command);
+    if let Err(e) = run(systemd_command).await {
+        debug!("nothing reset: {}",e);
+    }
+    Ok((config.into(),timer_spec))
+}
+
+/// Async version of [`query_registration()`](crate::query_registration).
+pub async fn query_registration<'a>(unit_name: impl Into<UnitName<'a>>) -> Result<(Command,TimerSpec),QueryError> {
+    query_registration_scoped(unit_name,Scope::User).await
+}
+
+/// Async version of [`query_registration_scoped()`](crate::query_registration_scoped).
+pub async fn query_registration_scoped<'a>(unit_name: impl Into<UnitName<'a>>, scope: Scope) -> Result<(Command,TimerSpec),QueryError> {
+    let (config, timer_spec) = query_with(scope,unit_name.into()).await?;
+    Ok((config.into(),timer_spec))
+}
+
+#[instrument(name = "query_registration", level = "debug", skip_all, fields(unit_name = %unit_name))]
+async fn query_with(scope: Scope, unit_name: UnitName<'_>) -> Result<(CommandConfig,TimerSpec),QueryError> {
+    debug!("querying registration");
+
+    let units = show_units(scope,&[&unit_name.timer_unit(),&unit_name.service_unit()],crate::QUERY_PROPERTIES).await?;
+    let (encoded_command, timer_spec) = crate::registration_from_units(units)?;
+    Ok((CommandConfig::decode_config(encoded_command)?,timer_spec))
+}
+
+async fn show_units(scope: Scope, units: &[&str], properties: &[&str]) -> Result<Vec<std::collections::BTreeMap<String,String>>,QueryError> {
+    let output = run(crate::show_command(Systemd::new(scope),units,properties)).await?;
+    Ok(crate::parse_show(&output))
+}
+
+/// Async version of [`run_command()`](crate::run_command).
+async fn run(command: Command) -> Result<Output,CommandError> {
+    crate::check_platform()?;
+    let mut command = tokio::process::Command::from(command);
+    match command.output().await {
+        Ok(output) if output.status.success() => Ok(output),
+        Ok(output) => Err(output.into()),
+        Err(e) => Err(crate::spawn_error(command.as_std(),e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn test_send() {
+        fn assert_send(_: impl Send) {}
+        let unit_name = UnitName::new("test-send").unwrap();
+        assert_send(register(Schedule::Calendar("daily".to_owned()),unit_name,Command::new("true")));
+        assert_send(deregister(unit_name));
+        assert_send(query_registration(unit_name));
+    }
+
+    #[test]
+    fn test_run() {
+        assert!(block_on(run(Command::new("true"))).is_ok());
+        assert!(matches!(block_on(run(Command::new("false"))),Err(CommandError::CommandFailed { .. })));
+        assert!(matches!(block_on(run(Command::new("systemd-wake-test-no-such-binary"))),Err(CommandError::BinaryNotFound(_))));
+    }
+}